@@ -2,6 +2,8 @@ use std::cell::RefCell;
 use std::fmt::{Display, Formatter};
 use std::rc::Rc;
 
+use crate::iterator::tree::TreeNode;
+
 #[derive(Debug)]
 pub struct File {
   name: String,
@@ -64,6 +66,10 @@ impl Directory {
     self.entries.push(entry);
   }
 
+  pub fn get_entries(&self) -> &[Rc<RefCell<Entry>>] {
+    &self.entries
+  }
+
   pub fn get_size(&self) -> usize {
     self.entries.iter().fold(0, |r, e| r + e.borrow().get_size())
   }
@@ -139,6 +145,15 @@ impl Entry {
   }
 }
 
+impl TreeNode for Rc<RefCell<Entry>> {
+  fn children(&self) -> Vec<Self> {
+    match self.borrow().as_directory() {
+      Some(d) => d.get_entries().to_vec(),
+      None => vec![],
+    }
+  }
+}
+
 #[cfg(test)]
 mod test {
   use std::borrow::BorrowMut;
@@ -219,4 +234,47 @@ mod test {
 
     rootdir.print_line();
   }
+
+  #[test]
+  fn test_tree_iterators() {
+    let entry = |e: Entry| Rc::new(RefCell::new(e));
+    let rootdir = entry(Entry::of_directory("root"));
+    let bindir = entry(Entry::of_directory("bin"));
+    let usrdir = entry(Entry::of_directory("usr"));
+    let yuki = entry(Entry::of_directory("yuki"));
+    let add = |dir: &Rc<RefCell<Entry>>, e: Rc<RefCell<Entry>>| {
+      (**dir).borrow_mut().as_directory_mut().unwrap().add(e);
+    };
+    add(&rootdir, bindir.clone());
+    add(&rootdir, usrdir.clone());
+    add(&bindir, entry(Entry::of_file("vi", 10000)));
+    add(&usrdir, yuki.clone());
+    add(&yuki, entry(Entry::of_file("diary.html", 100)));
+    add(&yuki, entry(Entry::of_file("Composite.java", 200)));
+
+    let names = |entries: Vec<Rc<RefCell<Entry>>>| {
+      entries
+        .iter()
+        .map(|e| e.borrow().get_name().to_owned())
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      names(rootdir.clone().preorder().collect()),
+      ["root", "bin", "vi", "usr", "yuki", "diary.html", "Composite.java"]
+    );
+    assert_eq!(
+      names(rootdir.clone().postorder().collect()),
+      ["vi", "bin", "diary.html", "Composite.java", "yuki", "usr", "root"]
+    );
+    assert_eq!(
+      names(rootdir.clone().level_order().collect()),
+      ["root", "bin", "usr", "vi", "yuki", "diary.html", "Composite.java"]
+    );
+    let size = rootdir
+      .clone()
+      .leaves()
+      .filter_map(|e| e.borrow().as_file().map(File::get_size))
+      .sum::<usize>();
+    assert_eq!(size, rootdir.borrow().get_size());
+  }
 }
@@ -2,13 +2,19 @@ use std::cell::RefCell;
 use std::fmt::{Debug, Display, Formatter};
 use std::rc::Rc;
 
+use crate::iterator::tree::TreeNode;
+
 trait EntryBase {
   fn print_line_with_prefix(&self, prefix: &str);
 }
 
-pub trait Entry: EntryBase + Display + Debug {
+pub trait Entry: EntryBase + Display + Debug + 'static {
   fn get_name(&self) -> &str;
   fn get_size(&self) -> usize;
+  // 子の型は階層ごとに異なるので、辿るときはdyn Entryに揃える
+  fn get_entries(&self) -> Vec<Rc<RefCell<dyn Entry>>> {
+    vec![]
+  }
   fn print_line(&self) {
     self.print_line_with_prefix("");
   }
@@ -94,6 +100,20 @@ impl<E: Entry> Entry for Directory<E> {
   fn get_size(&self) -> usize {
     self.entries.iter().fold(0, |r, e| r + e.borrow().get_size())
   }
+
+  fn get_entries(&self) -> Vec<Rc<RefCell<dyn Entry>>> {
+    self
+      .entries
+      .iter()
+      .map(|e| e.clone() as Rc<RefCell<dyn Entry>>)
+      .collect()
+  }
+}
+
+impl TreeNode for Rc<RefCell<dyn Entry>> {
+  fn children(&self) -> Vec<Self> {
+    self.borrow().get_entries()
+  }
 }
 
 #[cfg(test)]
@@ -156,4 +176,39 @@ mod test {
 
     rootdir.print_line();
   }
+
+  #[test]
+  fn test_tree_iterators() {
+    let bindir = Rc::new(RefCell::new(Directory::new("bin")));
+    (*bindir)
+      .borrow_mut()
+      .add(Rc::new(RefCell::new(File::new("vi", 10000))));
+    (*bindir)
+      .borrow_mut()
+      .add(Rc::new(RefCell::new(File::new("latex", 20000))));
+    let mut rootdir = Directory::new("root");
+    rootdir.add(bindir);
+    rootdir.add(Rc::new(RefCell::new(Directory::new("tmp"))));
+    let rootdir: Rc<RefCell<dyn Entry>> = Rc::new(RefCell::new(rootdir));
+
+    let names = |entries: Vec<Rc<RefCell<dyn Entry>>>| {
+      entries
+        .iter()
+        .map(|e| (**e).borrow().get_name().to_owned())
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      names(rootdir.clone().preorder().collect()),
+      ["root", "bin", "vi", "latex", "tmp"]
+    );
+    assert_eq!(
+      names(rootdir.clone().postorder().collect()),
+      ["vi", "latex", "bin", "tmp", "root"]
+    );
+    assert_eq!(
+      names(rootdir.clone().level_order().collect()),
+      ["root", "bin", "tmp", "vi", "latex"]
+    );
+    assert_eq!(names(rootdir.leaves().collect()), ["vi", "latex", "tmp"]);
+  }
 }
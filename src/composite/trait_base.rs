@@ -2,6 +2,8 @@ use std::cell::RefCell;
 use std::fmt::{Debug, Display, Formatter};
 use std::rc::Rc;
 
+use crate::iterator::tree::TreeNode;
+
 trait EntryBase {
   fn print_line_with_prefix(&self, prefix: &str);
 }
@@ -9,6 +11,9 @@ trait EntryBase {
 pub trait Entry: EntryBase + Display + Debug {
   fn get_name(&self) -> &str;
   fn get_size(&self) -> usize;
+  fn get_entries(&self) -> &[Rc<RefCell<dyn Entry>>] {
+    &[]
+  }
   fn print_line(&self) {
     self.print_line_with_prefix("");
   }
@@ -94,6 +99,16 @@ impl Entry for Directory {
   fn get_size(&self) -> usize {
     self.entries.iter().fold(0, |r, e| r + e.borrow().get_size())
   }
+
+  fn get_entries(&self) -> &[Rc<RefCell<dyn Entry>>] {
+    &self.entries
+  }
+}
+
+impl TreeNode for Rc<RefCell<dyn Entry>> {
+  fn children(&self) -> Vec<Self> {
+    self.borrow().get_entries().to_vec()
+  }
 }
 
 #[cfg(test)]
@@ -154,4 +169,39 @@ mod test {
 
     rootdir.print_line();
   }
+
+  #[test]
+  fn test_tree_iterators() {
+    let bindir = Rc::new(RefCell::new(Directory::new("bin")));
+    bindir.borrow_mut().add(Rc::new(RefCell::new(File::new("vi", 10000))));
+    bindir
+      .borrow_mut()
+      .add(Rc::new(RefCell::new(File::new("latex", 20000))));
+    let mut rootdir = Directory::new("root");
+    rootdir.add(bindir);
+    rootdir.add(Rc::new(RefCell::new(Directory::new("tmp"))));
+    rootdir.add(Rc::new(RefCell::new(File::new("README", 300))));
+    let rootdir: Rc<RefCell<dyn Entry>> = Rc::new(RefCell::new(rootdir));
+
+    let names = |entries: Vec<Rc<RefCell<dyn Entry>>>| {
+      entries
+        .iter()
+        .map(|e| e.borrow().get_name().to_owned())
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      names(rootdir.clone().preorder().collect()),
+      ["root", "bin", "vi", "latex", "tmp", "README"]
+    );
+    assert_eq!(
+      names(rootdir.clone().postorder().collect()),
+      ["vi", "latex", "bin", "tmp", "README", "root"]
+    );
+    assert_eq!(
+      names(rootdir.clone().level_order().collect()),
+      ["root", "bin", "tmp", "README", "vi", "latex"]
+    );
+    // 空のディレクトリも葉として数える
+    assert_eq!(names(rootdir.leaves().collect()), ["vi", "latex", "tmp", "README"]);
+  }
 }
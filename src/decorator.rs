@@ -1,2 +1,3 @@
 mod enum_base;
+mod generic_base;
 mod trait_base;
@@ -0,0 +1,144 @@
+use std::fmt;
+
+use super::trait_base::Display;
+
+#[derive(Debug)]
+pub struct FullBorder<D: Display> {
+  underlying: D,
+  columns: usize,
+  rows: u32,
+}
+
+impl<D: Display> FullBorder<D> {
+  pub fn new(underlying: D) -> Self {
+    let columns = 1 + underlying.get_columns() + 1;
+    let rows = 1 + underlying.get_rows() + 1;
+    Self {
+      underlying,
+      columns,
+      rows,
+    }
+  }
+}
+
+impl<D: Display> Display for FullBorder<D> {
+  fn get_columns(&self) -> usize {
+    self.columns
+  }
+
+  fn get_rows(&self) -> u32 {
+    self.rows
+  }
+
+  fn write_row(&self, row: u32, w: &mut dyn fmt::Write) -> fmt::Result {
+    if row == 0 || row == self.rows - 1 {
+      w.write_char('+')?;
+      for _ in 0..self.columns - 2 {
+        w.write_char('-')?;
+      }
+      w.write_char('+')
+    } else {
+      w.write_char('|')?;
+      self.underlying.write_row(row - 1, w)?;
+      w.write_char('|')
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct SideBorder<D: Display> {
+  underlying: D,
+  border_char: char,
+  columns: usize,
+}
+
+impl<D: Display> SideBorder<D> {
+  pub fn new(underlying: D, ch: char) -> Self {
+    let columns = 1 + underlying.get_columns() + 1;
+    Self {
+      underlying,
+      border_char: ch,
+      columns,
+    }
+  }
+}
+
+impl<D: Display> Display for SideBorder<D> {
+  fn get_columns(&self) -> usize {
+    self.columns
+  }
+
+  fn get_rows(&self) -> u32 {
+    self.underlying.get_rows()
+  }
+
+  fn write_row(&self, row: u32, w: &mut dyn fmt::Write) -> fmt::Result {
+    w.write_char(self.border_char)?;
+    self.underlying.write_row(row, w)?;
+    w.write_char(self.border_char)
+  }
+}
+
+pub trait DisplayExt: Display + Sized {
+  fn side_border(self, ch: char) -> SideBorder<Self> {
+    SideBorder::new(self, ch)
+  }
+
+  fn full_border(self) -> FullBorder<Self> {
+    FullBorder::new(self)
+  }
+}
+
+impl<D: Display> DisplayExt for D {}
+
+#[cfg(test)]
+mod test {
+  use std::rc::Rc;
+
+  use super::super::trait_base;
+  use super::super::trait_base::{DisplayRows, StringDisplay};
+  use super::*;
+
+  fn render(display: &dyn Display) -> String {
+    let mut out = Vec::new();
+    display.write_to(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn test() {
+    let b2: SideBorder<StringDisplay> = StringDisplay::new("Hello, world.").side_border('#');
+    let b3: FullBorder<SideBorder<StringDisplay>> = b2.full_border();
+    b3.show();
+    let b4 = StringDisplay::new("Hello, world.")
+      .full_border()
+      .side_border('*')
+      .full_border()
+      .full_border()
+      .side_border('/');
+    b4.show();
+    assert_eq!(b4.rows().len(), 7);
+  }
+
+  #[test]
+  fn test_same_output_as_trait_base() {
+    let generic = StringDisplay::new("Hello, world.")
+      .full_border()
+      .side_border('*')
+      .full_border()
+      .full_border()
+      .side_border('/');
+    let dynamic = trait_base::SideBorder::new(
+      Rc::new(trait_base::FullBorder::new(Rc::new(trait_base::FullBorder::new(
+        Rc::new(trait_base::SideBorder::new(
+          Rc::new(trait_base::FullBorder::new(Rc::new(StringDisplay::new(
+            "Hello, world.",
+          )))),
+          '*',
+        )),
+      )))),
+      '/',
+    );
+    assert_eq!(render(&generic), render(&dynamic));
+  }
+}
@@ -1,19 +1,140 @@
-use std::fmt::Debug;
+use std::cell::OnceCell;
+use std::fmt::{self, Debug};
+use std::io;
 use std::rc::Rc;
 
 pub trait Display: Debug {
   fn get_columns(&self) -> usize;
   fn get_rows(&self) -> u32;
-  fn get_row_text(&self, row: u32) -> String;
+  fn write_row(&self, row: u32, w: &mut dyn fmt::Write) -> fmt::Result;
+
+  fn get_row_text(&self, row: u32) -> String {
+    let mut s = String::with_capacity(self.get_columns());
+    self.write_row(row, &mut s).unwrap();
+    s
+  }
+
+  fn write_to(&self, w: &mut dyn io::Write) -> io::Result<()> {
+    for row in Rows::new(self) {
+      writeln!(w, "{}", row)?;
+    }
+    Ok(())
+  }
 
   fn show(&self) {
-    for i in 0..self.get_rows() {
-      let s = self.get_row_text(i);
-      println!("{}", s)
+    for row in Rows::new(self) {
+      println!("{}", row)
+    }
+  }
+}
+
+pub trait DisplayRows: Display {
+  fn rows(&self) -> Rows<'_, Self>;
+}
+
+impl<D: Display + ?Sized> DisplayRows for D {
+  fn rows(&self) -> Rows<'_, Self> {
+    Rows::new(self)
+  }
+}
+
+pub struct Row<'a, D: ?Sized> {
+  display: &'a D,
+  row: u32,
+}
+
+impl<D: Display + ?Sized> fmt::Display for Row<'_, D> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.display.write_row(self.row, f)
+  }
+}
+
+pub struct Rows<'a, D: ?Sized> {
+  display: &'a D,
+  row: u32,
+  rows: u32,
+}
+
+impl<'a, D: Display + ?Sized> Rows<'a, D> {
+  fn new(display: &'a D) -> Self {
+    Self {
+      display,
+      row: 0,
+      rows: display.get_rows(),
     }
   }
 }
 
+impl<'a, D: Display + ?Sized> Iterator for Rows<'a, D> {
+  type Item = Row<'a, D>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.row >= self.rows {
+      return None;
+    }
+    let row = Row {
+      display: self.display,
+      row: self.row,
+    };
+    self.row += 1;
+    Some(row)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let len = (self.rows - self.row) as usize;
+    (len, Some(len))
+  }
+}
+
+impl<D: Display + ?Sized> ExactSizeIterator for Rows<'_, D> {}
+
+fn write_repeat(w: &mut dyn fmt::Write, ch: char, count: usize) -> fmt::Result {
+  for _ in 0..count {
+    w.write_char(ch)?;
+  }
+  Ok(())
+}
+
+// 先頭のskip文字を読み飛ばし、続くtake文字だけを書き込むWriter
+struct Clip<'a> {
+  inner: &'a mut dyn fmt::Write,
+  skip: usize,
+  take: usize,
+  first_dropped: Option<char>,
+  dropped: usize,
+}
+
+impl<'a> Clip<'a> {
+  fn new(inner: &'a mut dyn fmt::Write, skip: usize, take: usize) -> Self {
+    Self {
+      inner,
+      skip,
+      take,
+      first_dropped: None,
+      dropped: 0,
+    }
+  }
+}
+
+impl fmt::Write for Clip<'_> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    for c in s.chars() {
+      if self.skip > 0 {
+        self.skip -= 1;
+      } else if self.take > 0 {
+        self.take -= 1;
+        self.inner.write_char(c)?;
+      } else {
+        if self.dropped == 0 {
+          self.first_dropped = Some(c);
+        }
+        self.dropped += 1;
+      }
+    }
+    Ok(())
+  }
+}
+
 #[derive(Debug)]
 pub struct StringDisplay(String);
 
@@ -25,18 +146,18 @@ impl StringDisplay {
 
 impl Display for StringDisplay {
   fn get_columns(&self) -> usize {
-    self.0.len()
+    self.0.chars().count()
   }
 
   fn get_rows(&self) -> u32 {
     1
   }
 
-  fn get_row_text(&self, row: u32) -> String {
+  fn write_row(&self, row: u32, w: &mut dyn fmt::Write) -> fmt::Result {
     if row != 0 {
       panic!("index of bounds");
     }
-    self.0.clone()
+    w.write_str(&self.0)
   }
 }
 
@@ -45,38 +166,40 @@ pub trait Border: Display {}
 #[derive(Debug)]
 pub struct FullBorder {
   underlying: Rc<dyn Display>,
+  columns: usize,
+  rows: u32,
 }
 
 impl FullBorder {
   pub fn new(underlying: Rc<dyn Display>) -> Self {
-    Self { underlying }
-  }
-
-  fn make_line(ch: char, count: usize) -> String {
-    let mut line = String::new();
-    for _ in 0..count {
-      line.push(ch);
+    let columns = 1 + underlying.get_columns() + 1;
+    let rows = 1 + underlying.get_rows() + 1;
+    Self {
+      underlying,
+      columns,
+      rows,
     }
-    line
   }
 }
 
 impl Display for FullBorder {
   fn get_columns(&self) -> usize {
-    1 + self.underlying.get_columns() + 1
+    self.columns
   }
 
   fn get_rows(&self) -> u32 {
-    1 + self.underlying.get_rows() + 1
+    self.rows
   }
 
-  fn get_row_text(&self, row: u32) -> String {
-    if row == 0 {
-      format!("+{}+", FullBorder::make_line('-', self.underlying.get_columns()))
-    } else if row == self.underlying.get_rows() + 1 {
-      format!("+{}+", FullBorder::make_line('-', self.underlying.get_columns()))
+  fn write_row(&self, row: u32, w: &mut dyn fmt::Write) -> fmt::Result {
+    if row == 0 || row == self.rows - 1 {
+      w.write_char('+')?;
+      write_repeat(w, '-', self.columns - 2)?;
+      w.write_char('+')
     } else {
-      format!("|{}|", self.underlying.get_row_text(row - 1))
+      w.write_char('|')?;
+      self.underlying.write_row(row - 1, w)?;
+      w.write_char('|')
     }
   }
 }
@@ -85,40 +208,308 @@ impl Display for FullBorder {
 pub struct SideBorder {
   underlying: Rc<dyn Display>,
   border_char: char,
+  columns: usize,
+  rows: u32,
 }
 
 impl SideBorder {
   pub fn new(underlying: Rc<dyn Display>, ch: char) -> Self {
+    let columns = 1 + underlying.get_columns() + 1;
+    let rows = underlying.get_rows();
     Self {
       underlying,
       border_char: ch,
+      columns,
+      rows,
     }
   }
 }
 
 impl Display for SideBorder {
   fn get_columns(&self) -> usize {
-    1 + self.underlying.get_columns() + 1
+    self.columns
   }
 
   fn get_rows(&self) -> u32 {
-    self.underlying.get_rows()
+    self.rows
   }
 
-  fn get_row_text(&self, row: u32) -> String {
-    format!(
-      "{}{}{}",
-      self.border_char,
-      self.underlying.get_row_text(row),
-      self.border_char
-    )
+  fn write_row(&self, row: u32, w: &mut dyn fmt::Write) -> fmt::Result {
+    w.write_char(self.border_char)?;
+    self.underlying.write_row(row, w)?;
+    w.write_char(self.border_char)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+  Wrap,
+  Truncate,
+}
+
+#[derive(Debug)]
+pub struct MaxWidth {
+  underlying: Rc<dyn Display>,
+  overflow: Overflow,
+  columns: usize,
+  lines: OnceCell<Vec<String>>,
+}
+
+impl MaxWidth {
+  pub fn new(underlying: Rc<dyn Display>, max_columns: usize, overflow: Overflow) -> Self {
+    let columns = underlying.get_columns().min(max_columns);
+    Self {
+      underlying,
+      overflow,
+      columns,
+      lines: OnceCell::new(),
+    }
+  }
+
+  fn wrap(s: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    if columns == 0 {
+      return vec![line];
+    }
+    for word in s.split_whitespace() {
+      let mut word: Vec<char> = word.chars().collect();
+      let line_len = line.chars().count();
+      if line_len > 0 && line_len + 1 + word.len() <= columns {
+        line.push(' ');
+        line.extend(word);
+        continue;
+      }
+      if line_len > 0 {
+        lines.push(std::mem::take(&mut line));
+      }
+      while word.len() > columns {
+        lines.push(word.drain(..columns).collect());
+      }
+      line.extend(word);
+    }
+    if !line.is_empty() || lines.is_empty() {
+      lines.push(line);
+    }
+    lines
+  }
+
+  fn lines(&self) -> &[String] {
+    self.lines.get_or_init(|| {
+      (0..self.underlying.get_rows())
+        .flat_map(|row| Self::wrap(&self.underlying.get_row_text(row), self.columns))
+        .collect()
+    })
+  }
+}
+
+impl Display for MaxWidth {
+  fn get_columns(&self) -> usize {
+    self.columns
+  }
+
+  fn get_rows(&self) -> u32 {
+    match self.overflow {
+      Overflow::Wrap => self.lines().len() as u32,
+      Overflow::Truncate => self.underlying.get_rows(),
+    }
+  }
+
+  fn write_row(&self, row: u32, w: &mut dyn fmt::Write) -> fmt::Result {
+    let written = match self.overflow {
+      Overflow::Wrap => {
+        let line = &self.lines()[row as usize];
+        w.write_str(line)?;
+        line.chars().count()
+      }
+      // 幅がなければ省略記号も書けない
+      Overflow::Truncate if self.columns == 0 => 0,
+      Overflow::Truncate => {
+        let mut clip = Clip::new(w, 0, self.columns.saturating_sub(1));
+        self.underlying.write_row(row, &mut clip)?;
+        let (written, first_dropped, dropped) = (
+          self.columns.saturating_sub(1) - clip.take,
+          clip.first_dropped,
+          clip.dropped,
+        );
+        match (first_dropped, dropped) {
+          (None, _) => written,
+          (Some(c), 1) => {
+            w.write_char(c)?;
+            written + 1
+          }
+          (Some(_), _) => {
+            w.write_char('…')?;
+            written + 1
+          }
+        }
+      }
+    };
+    write_repeat(w, ' ', self.columns - written)
+  }
+}
+
+#[derive(Debug)]
+pub struct MaxHeight {
+  underlying: Rc<dyn Display>,
+  max_rows: u32,
+  columns: usize,
+  underlying_rows: u32,
+}
+
+impl MaxHeight {
+  pub fn new(underlying: Rc<dyn Display>, max_rows: u32) -> Self {
+    let columns = underlying.get_columns();
+    let underlying_rows = underlying.get_rows();
+    Self {
+      underlying,
+      max_rows,
+      columns,
+      underlying_rows,
+    }
+  }
+
+  fn head_rows(&self) -> u32 {
+    self.max_rows / 2
+  }
+}
+
+impl Display for MaxHeight {
+  fn get_columns(&self) -> usize {
+    self.columns
+  }
+
+  fn get_rows(&self) -> u32 {
+    self.underlying_rows.min(self.max_rows)
+  }
+
+  fn write_row(&self, row: u32, w: &mut dyn fmt::Write) -> fmt::Result {
+    if self.underlying_rows <= self.max_rows || row < self.head_rows() {
+      self.underlying.write_row(row, w)
+    } else if row == self.head_rows() {
+      if self.columns == 0 {
+        return Ok(());
+      }
+      w.write_char('…')?;
+      write_repeat(w, ' ', self.columns - 1)
+    } else {
+      self
+        .underlying
+        .write_row(self.underlying_rows - (self.max_rows - row), w)
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct Viewport {
+  underlying: Rc<dyn Display>,
+  columns: usize,
+  rows: u32,
+  column_offset: usize,
+  row_offset: u32,
+  underlying_columns: usize,
+  underlying_rows: u32,
+}
+
+impl Viewport {
+  pub fn new(underlying: Rc<dyn Display>, columns: usize, rows: u32) -> Self {
+    let underlying_columns = underlying.get_columns();
+    let underlying_rows = underlying.get_rows();
+    Self {
+      underlying,
+      columns,
+      rows,
+      column_offset: 0,
+      row_offset: 0,
+      underlying_columns,
+      underlying_rows,
+    }
+  }
+
+  pub fn scroll_to(&mut self, row_offset: u32, column_offset: usize) {
+    self.row_offset = row_offset.min(self.underlying_rows.saturating_sub(self.rows));
+    self.column_offset = column_offset.min(self.underlying_columns.saturating_sub(self.columns));
+  }
+
+  pub fn scroll_by(&mut self, rows: i64, columns: i64) {
+    let row_offset = (self.row_offset as i64 + rows).max(0) as u32;
+    let column_offset = (self.column_offset as i64 + columns).max(0) as usize;
+    self.scroll_to(row_offset, column_offset);
+  }
+
+  pub fn row_offset(&self) -> u32 {
+    self.row_offset
+  }
+
+  pub fn column_offset(&self) -> usize {
+    self.column_offset
+  }
+}
+
+impl Display for Viewport {
+  fn get_columns(&self) -> usize {
+    self.columns
+  }
+
+  fn get_rows(&self) -> u32 {
+    self.rows
+  }
+
+  fn write_row(&self, row: u32, w: &mut dyn fmt::Write) -> fmt::Result {
+    if row >= self.rows {
+      panic!("index of bounds");
+    }
+    let underlying_row = self.row_offset + row;
+    if underlying_row >= self.underlying_rows {
+      return write_repeat(w, ' ', self.columns);
+    }
+    let mut clip = Clip::new(w, self.column_offset, self.columns);
+    self.underlying.write_row(underlying_row, &mut clip)?;
+    let remaining = clip.take;
+    write_repeat(w, ' ', remaining)
   }
 }
 
 #[cfg(test)]
 mod test {
+  extern crate test;
+
+  use std::cell::Cell;
+
+  use test::Bencher;
+
   use super::*;
 
+  #[derive(Debug, Default)]
+  struct CountingDisplay {
+    dimension_calls: Cell<usize>,
+    write_calls: Cell<usize>,
+  }
+
+  impl Display for CountingDisplay {
+    fn get_columns(&self) -> usize {
+      self.dimension_calls.set(self.dimension_calls.get() + 1);
+      3
+    }
+
+    fn get_rows(&self) -> u32 {
+      self.dimension_calls.set(self.dimension_calls.get() + 1);
+      2
+    }
+
+    fn write_row(&self, _row: u32, w: &mut dyn fmt::Write) -> fmt::Result {
+      self.write_calls.set(self.write_calls.get() + 1);
+      w.write_str("abc")
+    }
+  }
+
+  fn nest(leaf: Rc<dyn Display>, depth: usize) -> Rc<dyn Display> {
+    (0..depth).fold(leaf, |display, i| match i % 2 {
+      0 => Rc::new(FullBorder::new(display)),
+      _ => Rc::new(SideBorder::new(display, '*')),
+    })
+  }
+
   #[test]
   fn test() {
     let b1 = Rc::new(StringDisplay::new("Hello, world."));
@@ -136,4 +527,150 @@ mod test {
     );
     b4.show();
   }
+
+  fn render(display: &dyn Display) -> Vec<String> {
+    (0..display.get_rows()).map(|row| display.get_row_text(row)).collect()
+  }
+
+  #[test]
+  fn test_max_width() {
+    let text = Rc::new(StringDisplay::new("The quick brown fox jumps over the lazy dog"));
+    let wrapped = FullBorder::new(Rc::new(MaxWidth::new(text.clone(), 12, Overflow::Wrap)));
+    wrapped.show();
+    assert_eq!(
+      render(&wrapped),
+      vec![
+        "+------------+",
+        "|The quick   |",
+        "|brown fox   |",
+        "|jumps over  |",
+        "|the lazy dog|",
+        "+------------+",
+      ]
+    );
+
+    let truncated = SideBorder::new(Rc::new(MaxWidth::new(text, 12, Overflow::Truncate)), '#');
+    truncated.show();
+    assert_eq!(render(&truncated), vec!["#The quick b…#"]);
+
+    let long_word = MaxWidth::new(Rc::new(StringDisplay::new("abcdefghij")), 4, Overflow::Wrap);
+    assert_eq!(render(&long_word), vec!["abcd", "efgh", "ij  "]);
+  }
+
+  #[test]
+  fn test_zero_width() {
+    let text = Rc::new(StringDisplay::new("Hello"));
+    let truncated = MaxWidth::new(text.clone(), 0, Overflow::Truncate);
+    assert_eq!(render(&truncated), vec![""]);
+    let wrapped = MaxWidth::new(text, 0, Overflow::Wrap);
+    assert_eq!(render(&wrapped), vec![""]);
+
+    let rows = (0..5).fold(Rc::new(truncated) as Rc<dyn Display>, |display, _| {
+      Rc::new(FullBorder::new(display))
+    });
+    let clipped = MaxHeight::new(Rc::new(MaxWidth::new(rows, 0, Overflow::Truncate)), 3);
+    assert_eq!(render(&clipped), vec!["", "", ""]);
+  }
+
+  #[test]
+  fn test_max_height() {
+    let mut display: Rc<dyn Display> = Rc::new(StringDisplay::new("Hello, world."));
+    for _ in 0..4 {
+      display = Rc::new(FullBorder::new(display));
+    }
+    let clipped = MaxHeight::new(display, 5);
+    clipped.show();
+    assert_eq!(clipped.get_rows(), 5);
+    assert_eq!(
+      render(&clipped),
+      vec![
+        "+-------------------+",
+        "|+-----------------+|",
+        "…                    ",
+        "|+-----------------+|",
+        "+-------------------+",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_viewport() {
+    let mut display: Rc<dyn Display> = Rc::new(StringDisplay::new("Hello, world."));
+    for _ in 0..3 {
+      display = Rc::new(FullBorder::new(display));
+    }
+    let mut viewport = Viewport::new(display, 6, 3);
+    assert_eq!(render(&viewport), vec!["+-----", "|+----", "||+---"]);
+    viewport.scroll_by(2, 2);
+    assert_eq!(render(&viewport), vec!["+-----", "|Hello", "+-----"]);
+    viewport.scroll_to(100, 100);
+    assert_eq!((viewport.row_offset(), viewport.column_offset()), (4, 13));
+    assert_eq!(render(&viewport), vec!["---+||", "----+|", "-----+"]);
+  }
+
+  #[test]
+  fn test_fit_terminal() {
+    let lines = (0..100)
+      .map(|i| format!("line {} {}", i, "lorem ipsum ".repeat(i % 10)))
+      .collect::<Vec<_>>();
+    let text: Rc<dyn Display> = Rc::new(StringDisplay::new(&lines.join(" ")));
+    let wrapped = Rc::new(MaxWidth::new(text, 76, Overflow::Wrap));
+    let clipped = Rc::new(MaxHeight::new(wrapped, 22));
+    let screen = SideBorder::new(Rc::new(FullBorder::new(clipped)), '#');
+    screen.show();
+    assert_eq!(screen.get_rows(), 24);
+    assert!(render(&screen).iter().all(|row| row.chars().count() == 80));
+  }
+
+  #[test]
+  fn test_rows() {
+    let display = nest(Rc::new(StringDisplay::new("Hello, world.")), 5);
+    let mut out = Vec::new();
+    display.write_to(&mut out).unwrap();
+    let expected = display.rows().map(|row| format!("{}\n", row)).collect::<String>();
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+    assert_eq!(display.rows().len(), 7);
+    assert_eq!(display.rows().last().unwrap().to_string(), "+---------------------+");
+  }
+
+  #[test]
+  fn test_memoized_dimensions() {
+    let leaf = Rc::new(CountingDisplay::default());
+    let display = nest(leaf.clone(), 100);
+    let dimension_calls = leaf.dimension_calls.get();
+    let mut out = Vec::new();
+    display.write_to(&mut out).unwrap();
+    assert_eq!(leaf.dimension_calls.get(), dimension_calls);
+    assert_eq!(leaf.write_calls.get(), 2);
+  }
+
+  #[bench]
+  fn bench_nest_5(b: &mut Bencher) {
+    let display = nest(Rc::new(StringDisplay::new("Hello, world.")), 5);
+    let mut out = Vec::new();
+    b.iter(|| {
+      out.clear();
+      display.write_to(&mut out).unwrap();
+    });
+  }
+
+  #[bench]
+  fn bench_nest_50(b: &mut Bencher) {
+    let display = nest(Rc::new(StringDisplay::new("Hello, world.")), 50);
+    let mut out = Vec::new();
+    b.iter(|| {
+      out.clear();
+      display.write_to(&mut out).unwrap();
+    });
+  }
+
+  #[bench]
+  fn bench_nest_500(b: &mut Bencher) {
+    let display = nest(Rc::new(StringDisplay::new("Hello, world.")), 500);
+    let mut out = Vec::new();
+    b.iter(|| {
+      out.clear();
+      display.write_to(&mut out).unwrap();
+    });
+  }
 }
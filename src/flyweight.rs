@@ -1,108 +1,282 @@
-use std::collections::HashMap;
-use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::rc::Rc;
-use std::sync::Mutex;
-
-use anyhow::Result;
+mod font;
+mod layout;
+mod pool;
+
+use std::borrow::Cow;
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Range;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
 use once_cell::sync::OnceCell;
 
-#[derive(Debug)]
+pub use font::{Font, FontError};
+pub use layout::Layout;
+pub use pool::{FlyweightPool, PoolStats};
+
+// グリフの行は複製せず、共有しているフォントのソースから借用する
 pub struct BigChar {
   char_name: char,
-  font_data: String,
+  font: Font,
+  range: Range<usize>,
 }
 
-unsafe impl Send for BigChar {}
-
 impl Display for BigChar {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{}", self.font_data)
+    Layout::Vertical.render(&[&self.rows()[..]], f)
+  }
+}
+
+impl Debug for BigChar {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("BigChar")
+      .field("char_name", &self.char_name)
+      .field("range", &self.range)
+      .finish()
   }
 }
 
 impl BigChar {
-  fn read_font_data(char_name: char) -> Result<String> {
-    println!("read_font_data: {}", char_name);
-    let file_name = format!("flyweight/big{}.txt", char_name);
-    let mut reader = BufReader::new(File::open(file_name)?);
-    let mut line: String = String::new();
-    let mut buf = String::new();
-    while let Ok(size) = reader.read_line(&mut line) {
-      if size == 0 {
-        break;
-      }
-      buf.push_str(&line);
-      line.clear();
-    }
-    Ok(buf.to_string())
+  pub fn new(char_name: char, font: &Font) -> Result<Self, FontError> {
+    let range = font.glyph_range(char_name)?;
+    Ok(Self {
+      char_name,
+      font: font.clone(),
+      range,
+    })
   }
 
-  pub fn new(char_name: char) -> Self {
-    let font_data = Self::read_font_data(char_name).unwrap();
-    Self { char_name, font_data }
+  pub fn rows(&self) -> Vec<Cow<'_, str>> {
+    self.font.rows(&self.range).collect()
   }
 }
 
 #[derive(Debug)]
 pub struct BigCharFactory {
-  pool: HashMap<char, Rc<BigChar>>,
+  pool: FlyweightPool<char, BigChar, FontError>,
+  charset: Vec<char>,
 }
 
-unsafe impl Send for BigCharFactory {}
-
 impl BigCharFactory {
   pub fn new() -> Self {
-    Self { pool: HashMap::new() }
+    Self::with_font(Font::embedded())
+  }
+
+  pub fn global() -> &'static Arc<BigCharFactory> {
+    BIG_CHAR_FACTORY_SINGLETON.get_or_init(|| Arc::new(BigCharFactory::new()))
+  }
+
+  pub fn with_font(font: Font) -> Self {
+    let mut charset = font.chars().collect::<Vec<_>>();
+    charset.sort();
+    Self {
+      pool: FlyweightPool::new(move |char_name| BigChar::new(*char_name, &font)),
+      charset,
+    }
+  }
+
+  // preload_all で読み込む文字は、プールのローダーが扱える文字として渡す
+  pub fn with_pool(pool: FlyweightPool<char, BigChar, FontError>, charset: impl IntoIterator<Item = char>) -> Self {
+    let mut charset = charset.into_iter().collect::<Vec<_>>();
+    charset.sort();
+    charset.dedup();
+    Self { pool, charset }
+  }
+
+  pub fn get_big_char(&self, char_name: char) -> Result<Arc<BigChar>, FontError> {
+    self.pool.get(&char_name)
+  }
+
+  pub fn preload(&self, chars: impl IntoIterator<Item = char>) -> Result<usize, FontError> {
+    let mut chars = chars.into_iter().collect::<Vec<_>>();
+    chars.sort();
+    chars.dedup();
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = chars.len().div_ceil(threads).max(1);
+    thread::scope(|s| {
+      let handles = chars
+        .chunks(chunk_size)
+        .map(|chunk| s.spawn(move || chunk.iter().try_for_each(|c| self.get_big_char(*c).map(|_| ()))))
+        .collect::<Vec<_>>();
+      handles.into_iter().try_for_each(|h| h.join().unwrap())
+    })?;
+    Ok(chars.len())
+  }
+
+  pub fn preload_all(&self) -> Result<usize, FontError> {
+    self.preload(self.charset.iter().copied())
+  }
+
+  pub fn warm_up(self: &Arc<Self>) -> JoinHandle<Result<usize, FontError>> {
+    let factory = self.clone();
+    thread::spawn(move || factory.preload_all())
   }
 
-  pub fn get_big_char(&mut self, char_name: char) -> Rc<BigChar> {
-    let result = self
-      .pool
-      .entry(char_name)
-      .or_insert_with(|| Rc::new(BigChar::new(char_name)));
-    result.clone()
+  pub fn stats(&self) -> PoolStats {
+    self.pool.stats()
   }
 }
 
-pub static BIG_CHAR_FACTORY_SINGLETON: OnceCell<Mutex<BigCharFactory>> = OnceCell::new();
+pub static BIG_CHAR_FACTORY_SINGLETON: OnceCell<Arc<BigCharFactory>> = OnceCell::new();
 
 #[derive(Debug)]
 pub struct BigString {
-  big_chars: Vec<Rc<BigChar>>,
+  big_chars: Vec<Arc<BigChar>>,
+  layout: Layout,
 }
 
 impl Display for BigString {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-    let mut buf = String::new();
-    for bc in &self.big_chars {
-      buf = format!("{}{}", buf, bc);
-    }
-    write!(f, "{}", buf)
+    let rows = self.big_chars.iter().map(|bc| bc.rows()).collect::<Vec<_>>();
+    let glyphs = rows.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    self.layout.render(&glyphs, f)
   }
 }
 
 impl BigString {
-  pub fn new(string: &str) -> Self {
-    let mut big_chars = Vec::with_capacity(string.len());
-    let factory = BIG_CHAR_FACTORY_SINGLETON.get_or_init(|| Mutex::new(BigCharFactory::new()));
-    for (i, c) in string.chars().enumerate() {
-      let mut f = factory.lock().unwrap();
-      let bc = f.get_big_char(c);
-      big_chars.insert(i, bc);
-    }
-    Self { big_chars }
+  pub fn new(string: &str) -> Result<Self, FontError> {
+    Self::with_factory(string, BigCharFactory::global())
+  }
+
+  pub fn with_factory(string: &str, factory: &BigCharFactory) -> Result<Self, FontError> {
+    let big_chars = string
+      .chars()
+      .map(|c| factory.get_big_char(c))
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(Self {
+      big_chars,
+      layout: Layout::default(),
+    })
+  }
+
+  pub fn with_layout(mut self, layout: Layout) -> Self {
+    self.layout = layout;
+    self
   }
 }
 
 #[cfg(test)]
 mod test {
+  use super::layout::HorizontalLayout;
   use super::*;
 
   #[test]
   fn test() {
-    let bs = BigString::new("1928374650564738291");
+    let bs = BigString::new("1928374650564738291").unwrap();
     print!("{}", bs);
   }
+
+  #[test]
+  fn test_layout() {
+    let bs = BigString::new("10").unwrap();
+    let lines = bs.to_string().lines().map(str::to_owned).collect::<Vec<_>>();
+    assert_eq!(lines.len(), 8);
+    assert_eq!(lines[0], "......##............######......");
+
+    let vertical = bs.with_layout(Layout::Vertical).to_string();
+    assert_eq!(vertical.lines().count(), 16);
+    assert_eq!(vertical.lines().nth(8), Some("....######......"));
+
+    let kerned = BigString::new("10")
+      .unwrap()
+      .with_layout(Layout::Horizontal(
+        HorizontalLayout::new().with_blank('.').with_kerning().with_spacing(1),
+      ))
+      .to_string();
+    assert_eq!(kerned.lines().next(), Some("....##.......######.."));
+    // 空白を指定しなければ、同梱フォントの背景の '.' を詰める
+    let kerned_by_default = BigString::new("10")
+      .unwrap()
+      .with_layout(Layout::Horizontal(
+        HorizontalLayout::new().with_kerning().with_spacing(1),
+      ))
+      .to_string();
+    assert_eq!(kerned_by_default, kerned);
+
+    let wrapped = BigString::new("12345")
+      .unwrap()
+      .with_layout(Layout::Horizontal(
+        HorizontalLayout::new().with_spacing(2).with_max_width(40),
+      ))
+      .to_string();
+    let lines = wrapped.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 24);
+    assert_eq!(lines[0].len(), 34);
+    assert_eq!(lines[16].len(), 16);
+  }
+
+  #[test]
+  fn test_preload() {
+    let factory = Arc::new(BigCharFactory::with_font(
+      Font::map_file(concat!(env!("CARGO_MANIFEST_DIR"), "/flyweight/big.flf")).unwrap(),
+    ));
+    assert_eq!(factory.warm_up().join().unwrap().unwrap(), 12);
+    let stats = factory.stats();
+    assert_eq!((stats.misses, stats.live), (12, 12));
+    BigString::with_factory("2024-10-18", &factory).unwrap();
+    assert_eq!(factory.stats().misses, 12);
+
+    assert_eq!(factory.preload("0123".chars()).unwrap(), 4);
+    // マップしたソースの行をそのまま借用している
+    let seven = factory.get_big_char('7').unwrap();
+    assert!(seven.rows().iter().all(|row| matches!(row, Cow::Borrowed(_))));
+    assert_eq!(seven.rows(), Font::embedded().glyph('7').unwrap());
+
+    let font = Font::embedded();
+    let pool = FlyweightPool::new(move |c: &char| BigChar::new(*c, &font));
+    let factory = BigCharFactory::with_pool(pool, "3210".chars());
+    assert_eq!(factory.preload_all().unwrap(), 4);
+    assert_eq!(factory.stats().live, 4);
+    assert!(matches!(
+      factory.preload("0a".chars()),
+      Err(FontError::MissingGlyph('a'))
+    ));
+  }
+
+  #[test]
+  fn test_missing_glyph() {
+    assert!(matches!(BigString::new("12a"), Err(FontError::MissingGlyph('a'))));
+
+    let factory = BigCharFactory::with_font(Font::embedded().with_fallback('-'));
+    let bs = BigString::with_factory("1a", &factory)
+      .unwrap()
+      .with_layout(Layout::Vertical);
+    let dash = factory.get_big_char('-').unwrap();
+    assert_eq!(
+      bs.to_string(),
+      format!("{}{}", factory.get_big_char('1').unwrap(), dash)
+    );
+  }
+
+  #[test]
+  fn test_threads() {
+    let factory = BigCharFactory::new();
+    let sevens = std::thread::scope(|s| {
+      let handles = (0..16)
+        .map(|i| {
+          let factory = &factory;
+          s.spawn(move || {
+            let mut seven = None;
+            for j in 0..200 {
+              let digits = format!("{}-{}", i * j, 7_usize.pow((j % 5) as u32));
+              let bs = BigString::with_factory(&digits, factory).unwrap();
+              assert_eq!(bs.to_string().lines().count(), 8);
+              seven.get_or_insert_with(|| factory.get_big_char('7').unwrap());
+            }
+            seven.unwrap()
+          })
+        })
+        .collect::<Vec<_>>();
+      handles.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>()
+    });
+    assert!(sevens.iter().all(|bc| Arc::ptr_eq(bc, &sevens[0])));
+    assert_eq!(factory.stats().live, 11);
+
+    let handles = (0..8)
+      .map(|i| std::thread::spawn(move || BigString::new(&format!("{}", i * 1111)).unwrap()))
+      .collect::<Vec<_>>();
+    for h in handles {
+      h.join().unwrap();
+    }
+  }
 }
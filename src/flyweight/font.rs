@@ -0,0 +1,342 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::{fs, io};
+
+use memmap2::Mmap;
+
+const EMBEDDED_FONT: &str = include_str!("../../flyweight/big.flf");
+
+// FIGletではASCII 32..=126に続けてこのドイツ語の7文字が必須とされている
+const GERMAN_CHARS: [u32; 7] = [196, 214, 220, 228, 246, 252, 223];
+
+#[derive(Debug)]
+pub enum FontError {
+  Io(io::Error),
+  Parse { line: usize, reason: String },
+  MissingGlyph(char),
+}
+
+impl Display for FontError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      FontError::Io(e) => write!(f, "failed to read font: {}", e),
+      FontError::Parse { line, reason } => write!(f, "invalid font at line {}: {}", line, reason),
+      FontError::MissingGlyph(c) => write!(f, "no glyph for {:?}", c),
+    }
+  }
+}
+
+impl std::error::Error for FontError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      FontError::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for FontError {
+  fn from(e: io::Error) -> Self {
+    FontError::Io(e)
+  }
+}
+
+// UTF-8であることを検証したマップだけを保持する
+#[derive(Debug)]
+struct MappedText(Mmap);
+
+impl MappedText {
+  fn new(mmap: Mmap) -> Result<Self, FontError> {
+    if let Err(e) = std::str::from_utf8(&mmap) {
+      let line = mmap[..e.valid_up_to()].iter().filter(|b| **b == b'\n').count() + 1;
+      return Err(Font::parse_error(line, &e.to_string()));
+    }
+    Ok(Self(mmap))
+  }
+
+  fn as_str(&self) -> &str {
+    // Safety: newで一度だけ検証しており、マップは読み取り専用で書き換えられない
+    unsafe { std::str::from_utf8_unchecked(&self.0) }
+  }
+}
+
+#[derive(Debug)]
+enum FontSource {
+  Static(&'static str),
+  Owned(String),
+  Mapped(MappedText),
+}
+
+impl FontSource {
+  fn text(&self) -> &str {
+    match self {
+      FontSource::Static(text) => text,
+      FontSource::Owned(text) => text,
+      FontSource::Mapped(text) => text.as_str(),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Font {
+  source: Arc<FontSource>,
+  height: usize,
+  hard_blank: char,
+  // 複製してもソースと索引は共有する
+  glyphs: Arc<HashMap<char, Range<usize>>>,
+  fallback: Option<char>,
+}
+
+impl Font {
+  pub fn embedded() -> Self {
+    Self::index(FontSource::Static(EMBEDDED_FONT)).expect("the embedded font is valid")
+  }
+
+  pub fn from_file(path: impl AsRef<Path>) -> Result<Self, FontError> {
+    Self::parse(fs::read_to_string(path)?)
+  }
+
+  pub fn map_file(path: impl AsRef<Path>) -> Result<Self, FontError> {
+    let file = fs::File::open(path)?;
+    // Safety: フォントファイルは読み取り専用で開き、マップしている間は書き換えられない前提とする
+    let mmap = unsafe { Mmap::map(&file)? };
+    Self::index(FontSource::Mapped(MappedText::new(mmap)?))
+  }
+
+  pub fn parse(source: impl Into<String>) -> Result<Self, FontError> {
+    Self::index(FontSource::Owned(source.into()))
+  }
+
+  // グリフの行そのものは複製せず、ソース中の位置だけを記録する
+  fn index(source: FontSource) -> Result<Self, FontError> {
+    let text = source.text();
+    let mut offset = 0;
+    let mut lines = text
+      .split_inclusive('\n')
+      .enumerate()
+      .map(|(i, line)| {
+        let start = offset;
+        offset += line.len();
+        (i + 1, start, line)
+      })
+      .peekable();
+    let (_, _, header) = lines.next().ok_or_else(|| Self::parse_error(1, "missing header"))?;
+    let params = header
+      .strip_prefix("flf2a")
+      .ok_or_else(|| Self::parse_error(1, "header must start with flf2a"))?;
+    let hard_blank = params
+      .chars()
+      .next()
+      .ok_or_else(|| Self::parse_error(1, "missing hard blank"))?;
+    let numbers = params[hard_blank.len_utf8()..]
+      .split_whitespace()
+      .map(|s| s.parse::<i64>())
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|e| Self::parse_error(1, &e.to_string()))?;
+    let (height, comment_lines) = match numbers[..] {
+      [height, _, _, _, comment_lines, ..] if height > 0 && comment_lines >= 0 => {
+        (height as usize, comment_lines as usize)
+      }
+      _ => {
+        return Err(Self::parse_error(
+          1,
+          "expected height, baseline, max length, layout and comment lines",
+        ))
+      }
+    };
+    for _ in 0..comment_lines {
+      lines.next();
+    }
+
+    let mut glyphs = HashMap::new();
+    let mut required = (32..=126).chain(GERMAN_CHARS);
+    while let Some(&(line_no, _, line)) = lines.peek() {
+      let code = match required.next() {
+        Some(code) => i64::from(code),
+        // 必須のグリフより後ろの空行は読み飛ばす
+        None if line.trim().is_empty() => {
+          lines.next();
+          continue;
+        }
+        None => {
+          lines.next();
+          Self::parse_code_tag(line).ok_or_else(|| Self::parse_error(line_no, "invalid code tag"))?
+        }
+      };
+      let mut range = None::<Range<usize>>;
+      let mut empty = true;
+      for _ in 0..height {
+        let (_, start, row) = lines
+          .next()
+          .ok_or_else(|| Self::parse_error(line_no, "unexpected end of glyph"))?;
+        empty &= Self::row_text(row).is_empty();
+        range = Some(range.map_or(start, |r| r.start)..start + row.len());
+      }
+      // 行がすべて空のグリフは未定義として扱う
+      // 負のコードはFIGletでは使えるが、対応する文字がないので読み飛ばす
+      let c = u32::try_from(code).ok().and_then(char::from_u32);
+      if let (Some(c), Some(range), false) = (c, range, empty) {
+        glyphs.insert(c, range);
+      }
+    }
+    Ok(Self {
+      source: Arc::new(source),
+      height,
+      hard_blank,
+      glyphs: Arc::new(glyphs),
+      fallback: None,
+    })
+  }
+
+  fn parse_error(line: usize, reason: &str) -> FontError {
+    FontError::Parse {
+      line,
+      reason: reason.to_owned(),
+    }
+  }
+
+  fn parse_code_tag(line: &str) -> Option<i64> {
+    let code = line.split_whitespace().next()?;
+    let (sign, code) = match code.strip_prefix('-') {
+      Some(code) => (-1, code),
+      None => (1, code),
+    };
+    let code = if let Some(hex) = code.strip_prefix("0x").or_else(|| code.strip_prefix("0X")) {
+      i64::from_str_radix(hex, 16)
+    } else if code.len() > 1 && code.starts_with('0') {
+      i64::from_str_radix(&code[1..], 8)
+    } else {
+      code.parse()
+    };
+    code.ok().map(|code| sign * code)
+  }
+
+  fn row_text(row: &str) -> &str {
+    let row = row.trim_end();
+    match row.chars().last() {
+      Some(end_mark) => row.trim_end_matches(end_mark),
+      None => row,
+    }
+  }
+
+  pub fn with_fallback(mut self, fallback: char) -> Self {
+    self.fallback = Some(fallback);
+    self
+  }
+
+  pub fn height(&self) -> usize {
+    self.height
+  }
+
+  pub fn contains(&self, c: char) -> bool {
+    self.glyphs.contains_key(&c)
+  }
+
+  pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+    self.glyphs.keys().copied()
+  }
+
+  pub fn glyph(&self, c: char) -> Result<Vec<String>, FontError> {
+    let range = self.glyph_range(c)?;
+    Ok(self.rows(&range).map(Cow::into_owned).collect())
+  }
+
+  // フォールバックを解決したうえで、グリフがソースのどこにあるかを返す
+  pub(super) fn glyph_range(&self, c: char) -> Result<Range<usize>, FontError> {
+    self
+      .glyphs
+      .get(&c)
+      .or_else(|| self.fallback.and_then(|f| self.glyphs.get(&f)))
+      .cloned()
+      .ok_or(FontError::MissingGlyph(c))
+  }
+
+  // ハードブランクを含まない行は、ソースをそのまま借用して返す
+  pub(super) fn rows(&self, range: &Range<usize>) -> impl Iterator<Item = Cow<'_, str>> {
+    self.source.text()[range.clone()].lines().map(|row| {
+      let row = Self::row_text(row);
+      match row.contains(self.hard_blank) {
+        true => Cow::Owned(row.replace(self.hard_blank, " ")),
+        false => Cow::Borrowed(row),
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const BIG_FLF: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/flyweight/big.flf");
+  const NONE_FLF: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/flyweight/none.flf");
+
+  #[test]
+  fn test() {
+    let font = Font::embedded();
+    assert_eq!(font.height(), 8);
+    assert!(('0'..='9').all(|c| font.contains(c)));
+    assert_eq!(font.glyph('-').unwrap()[3], "..##########....");
+    assert!(matches!(font.glyph('A'), Err(FontError::MissingGlyph('A'))));
+    assert_eq!(font.with_fallback(' ').glyph('A').unwrap()[0], "................");
+  }
+
+  #[test]
+  fn test_parse() {
+    let source = "flf2a$ 2 2 4 -1 1\ncomment\n$$@\n$$@@\n#@\n#@@\n";
+    let font = Font::parse(source).unwrap();
+    assert_eq!(font.glyph(' ').unwrap(), ["  ", "  "]);
+    assert_eq!(font.glyph('!').unwrap(), ["#", "#"]);
+    assert!(!font.contains('"'));
+
+    let source = format!(
+      "flf2a$ 1 1 4 -1 0\n{}0x41 LATIN CAPITAL LETTER A\n/\\@@\n",
+      "@@\n".repeat(102)
+    );
+    let font = Font::parse(source).unwrap();
+    assert!(!font.contains(' '));
+    assert_eq!(font.glyph('A').unwrap(), ["/\\"]);
+
+    // 符号付きのコードタグと、末尾の空行を受け付ける
+    let source = format!(
+      "flf2a$ 1 1 4 -1 0\n{}-1 NEGATIVE\n-@@\n0x42\nB@@\n\n  \n",
+      "@@\n".repeat(102)
+    );
+    let font = Font::parse(source).unwrap();
+    assert_eq!(font.glyph('B').unwrap(), ["B"]);
+    assert_eq!(font.chars().count(), 1);
+
+    let truncated = "flf2a$ 2 2 4 -1 0\n$$@\n";
+    assert!(matches!(Font::parse(truncated), Err(FontError::Parse { line: 2, .. })));
+    assert!(matches!(Font::parse("figlet"), Err(FontError::Parse { line: 1, .. })));
+  }
+
+  #[test]
+  fn test_from_file() {
+    let font = Font::from_file(BIG_FLF).unwrap();
+    assert_eq!(font.glyph('7').unwrap(), Font::embedded().glyph('7').unwrap());
+    assert!(matches!(Font::from_file(NONE_FLF), Err(FontError::Io(_))));
+  }
+
+  #[test]
+  fn test_map_file() {
+    let font = Font::map_file(BIG_FLF).unwrap();
+    let embedded = Font::embedded();
+    let mut chars = font.chars().collect::<Vec<_>>();
+    chars.sort();
+    assert_eq!(chars, [' ', '-', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9']);
+    assert!(chars
+      .iter()
+      .all(|c| font.glyph(*c).unwrap() == embedded.glyph(*c).unwrap()));
+    assert!(matches!(Font::map_file(NONE_FLF), Err(FontError::Io(_))));
+
+    let path = std::env::temp_dir().join(format!("invalid-{}.flf", std::process::id()));
+    fs::write(&path, b"flf2a$ 1 1 4 -1 0\n@@\n\xff@@\n").unwrap();
+    let result = Font::map_file(&path);
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(result, Err(FontError::Parse { line: 3, .. })));
+  }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+  Vertical,
+  Horizontal(HorizontalLayout),
+}
+
+impl Default for Layout {
+  fn default() -> Self {
+    Layout::Horizontal(HorizontalLayout::new())
+  }
+}
+
+impl Layout {
+  pub fn render<R: AsRef<str>>(&self, glyphs: &[&[R]], w: &mut dyn fmt::Write) -> fmt::Result {
+    match self {
+      Layout::Vertical => {
+        for row in glyphs.iter().flat_map(|rows| rows.iter()) {
+          writeln!(w, "{}", row.as_ref())?;
+        }
+        Ok(())
+      }
+      Layout::Horizontal(layout) => layout.render(glyphs, w),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HorizontalLayout {
+  spacing: usize,
+  kerning: bool,
+  max_width: Option<usize>,
+  // None ならグリフの背景に最も多く使われている文字を空白とみなす
+  blank: Option<char>,
+}
+
+impl Default for HorizontalLayout {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl HorizontalLayout {
+  pub fn new() -> Self {
+    Self {
+      spacing: 0,
+      kerning: false,
+      max_width: None,
+      blank: None,
+    }
+  }
+
+  pub fn with_spacing(mut self, spacing: usize) -> Self {
+    self.spacing = spacing;
+    self
+  }
+
+  pub fn with_kerning(mut self) -> Self {
+    self.kerning = true;
+    self
+  }
+
+  pub fn with_max_width(mut self, max_width: usize) -> Self {
+    self.max_width = Some(max_width);
+    self
+  }
+
+  pub fn with_blank(mut self, blank: char) -> Self {
+    self.blank = Some(blank);
+    self
+  }
+
+  fn background<R: AsRef<str>>(glyphs: &[&[R]]) -> char {
+    let mut counts = HashMap::new();
+    for c in glyphs
+      .iter()
+      .flat_map(|rows| rows.iter())
+      .flat_map(|row| row.as_ref().chars())
+    {
+      *counts.entry(c).or_insert(0_usize) += 1;
+    }
+    counts
+      .into_iter()
+      .max_by_key(|(c, count)| (*count, *c == ' ', *c))
+      .map_or(' ', |(c, _)| c)
+  }
+
+  // グリフの各行を同じ幅に揃え、カーニングが有効なら左右の空白列を詰める
+  fn cells<R: AsRef<str>>(&self, rows: &[R], height: usize, blank: char) -> Vec<Vec<char>> {
+    let mut cells = (0..height)
+      .map(|i| {
+        rows
+          .get(i)
+          .map(|row| row.as_ref().chars().collect())
+          .unwrap_or_default()
+      })
+      .collect::<Vec<Vec<char>>>();
+    let width = cells.iter().map(Vec::len).max().unwrap_or(0);
+    for row in &mut cells {
+      row.resize(width, blank);
+    }
+    let blank_columns = |columns: &mut dyn Iterator<Item = usize>| {
+      columns
+        .take_while(|&column| cells.iter().all(|row| row[column] == blank))
+        .count()
+    };
+    let leading = blank_columns(&mut (0..width));
+    if !self.kerning || leading == width {
+      return cells;
+    }
+    let trailing = blank_columns(&mut (0..width).rev());
+    cells
+      .into_iter()
+      .map(|row| row[leading..width - trailing].to_vec())
+      .collect()
+  }
+
+  pub fn render<R: AsRef<str>>(&self, glyphs: &[&[R]], w: &mut dyn fmt::Write) -> fmt::Result {
+    let height = glyphs.iter().map(|rows| rows.len()).max().unwrap_or(0);
+    let blank = self.blank.unwrap_or_else(|| Self::background(glyphs));
+    let glyphs = glyphs
+      .iter()
+      .map(|rows| self.cells(rows, height, blank))
+      .collect::<Vec<_>>();
+    let mut lines: Vec<Vec<&Vec<Vec<char>>>> = vec![];
+    let mut line_width = 0;
+    for glyph in &glyphs {
+      let width = glyph.first().map(Vec::len).unwrap_or(0);
+      let spacing = if line_width == 0 { 0 } else { self.spacing };
+      match lines.last_mut() {
+        Some(line) if self.max_width.is_none_or(|max| line_width + spacing + width <= max) => {
+          line.push(glyph);
+          line_width += spacing + width;
+        }
+        _ => {
+          lines.push(vec![glyph]);
+          line_width = width;
+        }
+      }
+    }
+    for line in lines {
+      for row in 0..height {
+        for (i, glyph) in line.iter().enumerate() {
+          if i > 0 {
+            for _ in 0..self.spacing {
+              w.write_char(blank)?;
+            }
+          }
+          for c in &glyph[row] {
+            w.write_char(*c)?;
+          }
+        }
+        writeln!(w)?;
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,373 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::{Debug, Formatter};
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+
+const SHARD_COUNT: usize = 16;
+const MIN_PRUNE_LEN: usize = 16;
+
+type Loader<K, V, E> = Box<dyn Fn(&K) -> Result<V, E> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolStats {
+  pub hits: u64,
+  pub misses: u64,
+  pub live: usize,
+  pub evictions: u64,
+}
+
+enum Slot<V> {
+  Strong(Arc<V>),
+  Weak(Weak<V>),
+}
+
+impl<V> Slot<V> {
+  fn upgrade(&self) -> Option<Arc<V>> {
+    match self {
+      Slot::Strong(value) => Some(value.clone()),
+      Slot::Weak(value) => value.upgrade(),
+    }
+  }
+
+  fn is_alive(&self) -> bool {
+    match self {
+      Slot::Strong(_) => true,
+      Slot::Weak(value) => value.strong_count() > 0,
+    }
+  }
+
+  // プールの外でまだ使われているかどうか。使われている値を追い出すと同じキーの値が2つできてしまう
+  fn is_held(&self) -> bool {
+    match self {
+      Slot::Strong(value) => Arc::strong_count(value) > 1,
+      Slot::Weak(value) => value.strong_count() > 0,
+    }
+  }
+}
+
+struct Entry<V> {
+  slot: Slot<V>,
+  last_used: AtomicU64,
+}
+
+struct ShardMap<K, V> {
+  entries: HashMap<K, Entry<V>>,
+  // 回収済みの弱参照エントリは、前回の掃除から倍に増えたときにまとめて取り除く
+  prune_at: usize,
+}
+
+impl<K, V> ShardMap<K, V> {
+  fn new() -> Self {
+    Self {
+      entries: HashMap::new(),
+      prune_at: MIN_PRUNE_LEN,
+    }
+  }
+
+  fn prune(&mut self) -> usize {
+    let len = self.entries.len();
+    self.entries.retain(|_, entry| entry.slot.is_alive());
+    self.prune_at = (self.entries.len() * 2).max(MIN_PRUNE_LEN);
+    len - self.entries.len()
+  }
+}
+
+type Shard<K, V> = RwLock<ShardMap<K, V>>;
+
+pub struct FlyweightPool<K, V, E = Infallible> {
+  loader: Loader<K, V, E>,
+  hasher: RandomState,
+  shards: Vec<Shard<K, V>>,
+  weak_entries: bool,
+  max_entries: Option<usize>,
+  clock: AtomicU64,
+  hits: AtomicU64,
+  misses: AtomicU64,
+  evictions: AtomicU64,
+}
+
+impl<K: Hash + Eq + Clone, V, E> FlyweightPool<K, V, E> {
+  pub fn new(loader: impl Fn(&K) -> Result<V, E> + Send + Sync + 'static) -> Self {
+    Self {
+      loader: Box::new(loader),
+      hasher: RandomState::new(),
+      shards: (0..SHARD_COUNT).map(|_| RwLock::new(ShardMap::new())).collect(),
+      weak_entries: false,
+      max_entries: None,
+      clock: AtomicU64::new(0),
+      hits: AtomicU64::new(0),
+      misses: AtomicU64::new(0),
+      evictions: AtomicU64::new(0),
+    }
+  }
+
+  pub fn with_weak_entries(mut self) -> Self {
+    self.weak_entries = true;
+    self
+  }
+
+  // 上限と LRU はプール全体ではなくシャードごとに max_entries / シャード数 で効く。
+  // 小さい上限ではシャードを減らして精度を保つ。外で使われている値は追い出さないので、一時的に上限を超えることがある
+  pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+    let shard_count = (max_entries / SHARD_COUNT).clamp(1, SHARD_COUNT);
+    self.shards = (0..shard_count).map(|_| RwLock::new(ShardMap::new())).collect();
+    self.max_entries = Some(max_entries);
+    self
+  }
+
+  fn shard_capacity(&self) -> Option<usize> {
+    self.max_entries.map(|max_entries| max_entries / self.shards.len())
+  }
+
+  fn shard(&self, key: &K) -> &Shard<K, V> {
+    &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()]
+  }
+
+  fn tick(&self) -> u64 {
+    self.clock.fetch_add(1, Ordering::Relaxed)
+  }
+
+  pub fn get(&self, key: &K) -> Result<Arc<V>, E> {
+    let shard = self.shard(key);
+    if let Some(entry) = shard.read().unwrap().entries.get(key) {
+      if let Some(value) = entry.slot.upgrade() {
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        return Ok(value);
+      }
+    }
+    self.misses.fetch_add(1, Ordering::Relaxed);
+    // ローダーはロックの外で呼び出し、競合した場合は先に登録された値を使う
+    let loaded = Arc::new((self.loader)(key)?);
+    let value = {
+      let mut map = shard.write().unwrap();
+      if let Some(value) = map.entries.get(key).and_then(|entry| entry.slot.upgrade()) {
+        value
+      } else {
+        let slot = match self.weak_entries {
+          true => Slot::Weak(Arc::downgrade(&loaded)),
+          false => Slot::Strong(loaded.clone()),
+        };
+        let entry = Entry {
+          slot,
+          last_used: AtomicU64::new(self.tick()),
+        };
+        if self.weak_entries && map.entries.len() >= map.prune_at {
+          map.prune();
+        }
+        map.entries.insert(key.clone(), entry);
+        self.evict_overflow(&mut map);
+        loaded
+      }
+    };
+    Ok(value)
+  }
+
+  pub fn contains(&self, key: &K) -> bool {
+    let map = self.shard(key).read().unwrap();
+    map.entries.get(key).map(|entry| entry.slot.is_alive()).unwrap_or(false)
+  }
+
+  pub fn len(&self) -> usize {
+    self
+      .shards
+      .iter()
+      .map(|shard| shard.read().unwrap().entries.len())
+      .sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn purge(&self) -> usize {
+    self.shards.iter().map(|shard| shard.write().unwrap().prune()).sum()
+  }
+
+  // 挿入したシャードの中だけで、回収済みのものを取り除いてから、使われていない最も古いものを追い出す
+  fn evict_overflow(&self, map: &mut ShardMap<K, V>) {
+    let Some(capacity) = self.shard_capacity() else {
+      return;
+    };
+    if map.entries.len() > capacity && self.weak_entries {
+      map.prune();
+    }
+    while map.entries.len() > capacity {
+      let oldest = map
+        .entries
+        .iter()
+        .filter(|(_, entry)| !entry.slot.is_held())
+        .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+        .map(|(key, _)| key.clone());
+      let Some(key) = oldest else {
+        break;
+      };
+      map.entries.remove(&key);
+      self.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  pub fn stats(&self) -> PoolStats {
+    let live = self
+      .shards
+      .iter()
+      .map(|shard| {
+        shard
+          .read()
+          .unwrap()
+          .entries
+          .values()
+          .filter(|entry| entry.slot.is_alive())
+          .count()
+      })
+      .sum();
+    PoolStats {
+      hits: self.hits.load(Ordering::Relaxed),
+      misses: self.misses.load(Ordering::Relaxed),
+      live,
+      evictions: self.evictions.load(Ordering::Relaxed),
+    }
+  }
+}
+
+impl<K: Hash + Eq + Clone, V> FlyweightPool<K, V> {
+  pub fn intern(&self, key: &K) -> Arc<V> {
+    match self.get(key) {
+      Ok(value) => value,
+      Err(e) => match e {},
+    }
+  }
+}
+
+impl<K: Hash + Eq + Clone, V, E> Debug for FlyweightPool<K, V, E> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("FlyweightPool")
+      .field("weak_entries", &self.weak_entries)
+      .field("max_entries", &self.max_entries)
+      .field("stats", &self.stats())
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test() {
+    let pool = FlyweightPool::new(|s: &String| Ok::<_, Infallible>(s.to_uppercase()));
+    let a1 = pool.intern(&"icon".to_owned());
+    let a2 = pool.intern(&"icon".to_owned());
+    let b = pool.intern(&"config".to_owned());
+    assert!(Arc::ptr_eq(&a1, &a2));
+    assert_eq!(*b, "CONFIG");
+    assert_eq!(
+      pool.stats(),
+      PoolStats {
+        hits: 1,
+        misses: 2,
+        live: 2,
+        evictions: 0,
+      }
+    );
+  }
+
+  #[test]
+  fn test_loader_error() {
+    let pool = FlyweightPool::new(|n: &u32| if *n < 10 { Ok(n * 2) } else { Err(*n) });
+    assert_eq!(*pool.get(&4).unwrap(), 8);
+    assert_eq!(pool.get(&12), Err(12));
+    assert!(!pool.contains(&12));
+    assert_eq!(pool.len(), 1);
+  }
+
+  #[test]
+  fn test_weak_entries() {
+    let pool = FlyweightPool::new(|n: &u32| Ok::<_, Infallible>(vec![*n; 4])).with_weak_entries();
+    let a = pool.intern(&1);
+    let b = pool.intern(&2);
+    assert_eq!(pool.stats().live, 2);
+    drop(a);
+    assert!(!pool.contains(&1));
+    assert_eq!(pool.stats().live, 1);
+    assert_eq!(pool.purge(), 1);
+    assert!(Arc::ptr_eq(&b, &pool.intern(&2)));
+    pool.intern(&1);
+    assert_eq!(pool.stats().misses, 3);
+
+    // purge を呼ばなくても、回収済みのエントリはシャードが倍に増えるたびに取り除かれる
+    for n in 0..1000 {
+      pool.intern(&n);
+    }
+    assert!(pool.len() <= SHARD_COUNT * MIN_PRUNE_LEN);
+    assert!(pool.contains(&2));
+  }
+
+  #[test]
+  fn test_max_entries() {
+    let pool = FlyweightPool::new(|n: &u32| Ok::<_, Infallible>(*n)).with_max_entries(2);
+    pool.intern(&1);
+    pool.intern(&2);
+    pool.intern(&1);
+    pool.intern(&3);
+    assert!(pool.contains(&1));
+    assert!(!pool.contains(&2));
+    assert!(pool.contains(&3));
+    assert_eq!(
+      pool.stats(),
+      PoolStats {
+        hits: 1,
+        misses: 3,
+        live: 2,
+        evictions: 1,
+      }
+    );
+
+    let pool = FlyweightPool::new(|n: &u32| Ok::<_, Infallible>(*n)).with_max_entries(64);
+    std::thread::scope(|s| {
+      for t in 0..4 {
+        let pool = &pool;
+        s.spawn(move || {
+          for n in 0..250 {
+            pool.intern(&(t * 250 + n));
+          }
+        });
+      }
+    });
+    assert!(pool.len() <= 64);
+    assert_eq!(pool.stats().evictions, 1000 - pool.len() as u64);
+  }
+
+  #[test]
+  fn test_held_entries() {
+    // 使われている値は上限を超えても追い出さず、同じキーには同じ値を返し続ける
+    let pool = FlyweightPool::new(|n: &u32| Ok::<_, Infallible>(*n)).with_max_entries(2);
+    let one = pool.intern(&1);
+    let two = pool.intern(&2);
+    let three = pool.intern(&3);
+    assert_eq!(pool.len(), 3);
+    assert_eq!(pool.stats().evictions, 0);
+    assert!(Arc::ptr_eq(&one, &pool.intern(&1)));
+
+    drop(one);
+    let _four = pool.intern(&4);
+    assert!(!pool.contains(&1));
+    assert!(Arc::ptr_eq(&two, &pool.intern(&2)));
+    assert!(Arc::ptr_eq(&three, &pool.intern(&3)));
+    assert_eq!(pool.stats().evictions, 1);
+
+    // 弱参照でも、回収済みのものから先に取り除く
+    let pool = FlyweightPool::new(|n: &u32| Ok::<_, Infallible>(*n))
+      .with_weak_entries()
+      .with_max_entries(2);
+    let one = pool.intern(&1);
+    pool.intern(&2);
+    let _three = pool.intern(&3);
+    assert!(Arc::ptr_eq(&one, &pool.intern(&1)));
+    assert!(!pool.contains(&2));
+    assert_eq!(pool.stats().evictions, 0);
+  }
+}
@@ -1,65 +1,249 @@
-#[derive(Debug, Clone)]
+pub mod catalogue;
+pub mod page;
+mod search;
+pub mod tree;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::FusedIterator;
+use std::ops::{Deref, DerefMut, Index};
+use std::sync::RwLock;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use search::Indexes;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Book {
   name: String,
+  #[serde(default, deserialize_with = "non_empty")]
+  author: Option<String>,
+  #[serde(default, deserialize_with = "non_empty")]
+  isbn: Option<String>,
+  #[serde(default)]
+  year: Option<u16>,
+}
+
+// CSVでは空文字列と未設定を区別できないため、空文字列は常に未設定として扱う
+fn non_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+  Ok(Option::<String>::deserialize(deserializer)?.filter(|s| !s.is_empty()))
 }
 
 impl Book {
   pub fn new(name: &str) -> Self {
-    Self { name: name.to_owned() }
+    Self {
+      name: name.to_owned(),
+      author: None,
+      isbn: None,
+      year: None,
+    }
+  }
+
+  pub fn with_author(mut self, author: &str) -> Self {
+    self.author = Some(author.to_owned()).filter(|s| !s.is_empty());
+    self
+  }
+
+  pub fn with_isbn(mut self, isbn: &str) -> Self {
+    self.isbn = Some(isbn.to_owned()).filter(|s| !s.is_empty());
+    self
+  }
+
+  pub fn with_year(mut self, year: u16) -> Self {
+    self.year = Some(year);
+    self
   }
 
   pub fn name(&self) -> &str {
     &self.name
   }
+
+  pub fn author(&self) -> Option<&str> {
+    self.author.as_deref()
+  }
+
+  pub fn isbn(&self) -> Option<&str> {
+    self.isbn.as_deref()
+  }
+
+  pub fn year(&self) -> Option<u16> {
+    self.year
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+  id: u64,
+  book: Book,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct BookShelf {
-  values: Vec<Book>,
-  last: usize,
+  values: Vec<Slot>,
+  positions: HashMap<u64, usize>,
+  next_id: u64,
+  // 最初の検索時に作られ、以降は変更された本の分だけ更新される
+  indexes: RwLock<Option<Indexes>>,
 }
 
 impl BookShelf {
   pub fn new(capacity: usize) -> Self {
     Self {
       values: Vec::with_capacity(capacity),
-      last: 0,
+      ..Self::default()
     }
   }
 
   pub fn with_elements(values: &[Book]) -> Self {
-    Self {
-      values: values.to_vec(),
-      last: 0,
+    values.iter().cloned().collect()
+  }
+
+  fn slot(&mut self, book: Book) -> Slot {
+    let id = self.next_id;
+    self.next_id += 1;
+    Slot { id, book }
+  }
+
+  // index以降の本の位置を振り直す
+  fn update_positions_from(&mut self, index: usize) {
+    for (position, slot) in self.values.iter().enumerate().skip(index) {
+      self.positions.insert(slot.id, position);
     }
   }
 
-  pub fn get_book_at(&self, index: usize) -> &Book {
-    &self.values[index]
+  pub fn get(&self, index: usize) -> Option<&Book> {
+    self.values.get(index).map(|slot| &slot.book)
+  }
+
+  fn indexes_mut(&mut self) -> Option<&mut Indexes> {
+    self.indexes.get_mut().unwrap().as_mut()
+  }
+
+  pub fn get_mut(&mut self, index: usize) -> Option<BookMut<'_>> {
+    let indexed = self.indexes_mut().is_some();
+    let indexes = &self.indexes;
+    self
+      .values
+      .get_mut(index)
+      .map(|slot| BookMut::new(slot, indexes, indexed))
   }
 
   pub fn append_book(&mut self, book: Book) {
-    self.values.push(book);
-    self.last += 1;
+    let slot = self.slot(book);
+    if let Some(indexes) = self.indexes_mut() {
+      indexes.add(&slot);
+    }
+    self.positions.insert(slot.id, self.values.len());
+    self.values.push(slot);
+  }
+
+  pub fn insert(&mut self, index: usize, book: Book) {
+    let slot = self.slot(book);
+    // 範囲外ならここでパニックするので、索引には何も残らない
+    self.values.insert(index, slot);
+    if let Some(indexes) = self.indexes.get_mut().unwrap() {
+      indexes.add(&self.values[index]);
+    }
+    self.update_positions_from(index);
+  }
+
+  pub fn remove(&mut self, index: usize) -> Option<Book> {
+    if index >= self.values.len() {
+      return None;
+    }
+    let slot = self.values.remove(index);
+    if let Some(indexes) = self.indexes_mut() {
+      indexes.remove(&slot);
+    }
+    self.positions.remove(&slot.id);
+    self.update_positions_from(index);
+    Some(slot.book)
   }
 
   pub fn get_length(&self) -> usize {
-    self.last
+    self.len()
+  }
+
+  pub fn len(&self) -> usize {
+    self.values.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.values.is_empty()
+  }
+
+  pub fn sort_by_name(&mut self) {
+    self.values.sort_by(|a, b| a.book.name.cmp(&b.book.name));
+    self.update_positions_from(0);
   }
 
-  pub fn iter(&self) -> BookShelfIterator {
+  pub fn iter(&self) -> BookShelfIterator<'_> {
     BookShelfIterator::new(self)
   }
+
+  pub fn iter_mut(&mut self) -> BookShelfIterMut<'_> {
+    let indexed = self.indexes_mut().is_some();
+    BookShelfIterMut {
+      values: self.values.iter_mut(),
+      indexes: &self.indexes,
+      indexed,
+    }
+  }
+
+  pub fn filter<P: FnMut(&Book) -> bool>(&self, predicate: P) -> BookShelfFilter<'_, P> {
+    BookShelfFilter {
+      iter: self.iter(),
+      predicate,
+    }
+  }
+
+  pub fn chunks(&self, size: usize) -> BookShelfChunks<'_> {
+    assert!(size > 0, "chunk size must be non-zero");
+    BookShelfChunks {
+      book_shelf: self,
+      index: 0,
+      size,
+    }
+  }
+}
+
+impl Index<usize> for BookShelf {
+  type Output = Book;
+
+  fn index(&self, index: usize) -> &Self::Output {
+    &self.values[index].book
+  }
+}
+
+impl FromIterator<Book> for BookShelf {
+  fn from_iter<T: IntoIterator<Item = Book>>(iter: T) -> Self {
+    let mut book_shelf = Self::default();
+    book_shelf.extend(iter);
+    book_shelf
+  }
+}
+
+impl Extend<Book> for BookShelf {
+  fn extend<T: IntoIterator<Item = Book>>(&mut self, iter: T) {
+    for book in iter {
+      self.append_book(book);
+    }
+  }
 }
 
 pub struct BookShelfIterator<'a> {
   book_shelf: &'a BookShelf,
   index: usize,
+  end: usize,
 }
 
 impl<'a> BookShelfIterator<'a> {
   pub fn new(book_shelf: &'a BookShelf) -> Self {
-    Self { book_shelf, index: 0 }
+    Self::with_range(book_shelf, 0, book_shelf.values.len())
+  }
+
+  fn with_range(book_shelf: &'a BookShelf, index: usize, end: usize) -> Self {
+    Self { book_shelf, index, end }
   }
 }
 
@@ -67,15 +251,205 @@ impl<'a> Iterator for BookShelfIterator<'a> {
   type Item = &'a Book;
 
   fn next(&mut self) -> Option<Self::Item> {
-    match self.index < self.book_shelf.values.len() {
+    match self.index < self.end {
       true => {
-        let t = Some(&self.book_shelf.values[self.index]);
+        let t = Some(&self.book_shelf.values[self.index].book);
         self.index += 1;
         t
       }
       false => None,
     }
   }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let len = self.end - self.index;
+    (len, Some(len))
+  }
+
+  fn nth(&mut self, n: usize) -> Option<Self::Item> {
+    self.index = self.index.saturating_add(n).min(self.end);
+    self.next()
+  }
+}
+
+impl DoubleEndedIterator for BookShelfIterator<'_> {
+  fn next_back(&mut self) -> Option<Self::Item> {
+    match self.index < self.end {
+      true => {
+        self.end -= 1;
+        Some(&self.book_shelf.values[self.end].book)
+      }
+      false => None,
+    }
+  }
+
+  fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+    self.end = self.end.saturating_sub(n).max(self.index);
+    self.next_back()
+  }
+}
+
+impl ExactSizeIterator for BookShelfIterator<'_> {}
+
+impl FusedIterator for BookShelfIterator<'_> {}
+
+// 書き換えが終わった時点で、その本の索引だけを更新する
+pub struct BookMut<'a> {
+  slot: &'a mut Slot,
+  indexes: &'a RwLock<Option<Indexes>>,
+  // 索引がまだ作られていなければ、更新するものもない
+  before: Option<Book>,
+}
+
+impl<'a> BookMut<'a> {
+  fn new(slot: &'a mut Slot, indexes: &'a RwLock<Option<Indexes>>, indexed: bool) -> Self {
+    let before = indexed.then(|| slot.book.clone());
+    Self { slot, indexes, before }
+  }
+}
+
+impl fmt::Debug for BookMut<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.slot.book.fmt(f)
+  }
+}
+
+impl Deref for BookMut<'_> {
+  type Target = Book;
+
+  fn deref(&self) -> &Self::Target {
+    &self.slot.book
+  }
+}
+
+impl DerefMut for BookMut<'_> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.slot.book
+  }
+}
+
+impl Drop for BookMut<'_> {
+  fn drop(&mut self) {
+    let Some(before) = self.before.take() else {
+      return;
+    };
+    if before.name == self.slot.book.name && before.isbn == self.slot.book.isbn {
+      return;
+    }
+    if let Some(indexes) = self.indexes.write().unwrap().as_mut() {
+      indexes.remove(&Slot {
+        id: self.slot.id,
+        book: before,
+      });
+      indexes.add(self.slot);
+    }
+  }
+}
+
+pub struct BookShelfIterMut<'a> {
+  values: std::slice::IterMut<'a, Slot>,
+  indexes: &'a RwLock<Option<Indexes>>,
+  indexed: bool,
+}
+
+impl<'a> BookShelfIterMut<'a> {
+  fn book_mut(&self, slot: &'a mut Slot) -> BookMut<'a> {
+    BookMut::new(slot, self.indexes, self.indexed)
+  }
+}
+
+impl<'a> Iterator for BookShelfIterMut<'a> {
+  type Item = BookMut<'a>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.values.next().map(|slot| self.book_mut(slot))
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.values.size_hint()
+  }
+
+  fn nth(&mut self, n: usize) -> Option<Self::Item> {
+    self.values.nth(n).map(|slot| self.book_mut(slot))
+  }
+}
+
+impl DoubleEndedIterator for BookShelfIterMut<'_> {
+  fn next_back(&mut self) -> Option<Self::Item> {
+    self.values.next_back().map(|slot| self.book_mut(slot))
+  }
+
+  fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+    self.values.nth_back(n).map(|slot| self.book_mut(slot))
+  }
+}
+
+impl ExactSizeIterator for BookShelfIterMut<'_> {}
+
+impl FusedIterator for BookShelfIterMut<'_> {}
+
+pub struct BookShelfFilter<'a, P> {
+  iter: BookShelfIterator<'a>,
+  predicate: P,
+}
+
+impl<'a, P: FnMut(&Book) -> bool> Iterator for BookShelfFilter<'a, P> {
+  type Item = &'a Book;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.iter.by_ref().find(|book| (self.predicate)(book))
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    (0, self.iter.size_hint().1)
+  }
+}
+
+impl<P: FnMut(&Book) -> bool> DoubleEndedIterator for BookShelfFilter<'_, P> {
+  fn next_back(&mut self) -> Option<Self::Item> {
+    self.iter.by_ref().rfind(|book| (self.predicate)(book))
+  }
+}
+
+impl<P: FnMut(&Book) -> bool> FusedIterator for BookShelfFilter<'_, P> {}
+
+pub struct BookShelfChunks<'a> {
+  book_shelf: &'a BookShelf,
+  index: usize,
+  size: usize,
+}
+
+impl<'a> Iterator for BookShelfChunks<'a> {
+  type Item = BookShelfIterator<'a>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let len = self.book_shelf.values.len();
+    if self.index >= len {
+      return None;
+    }
+    let end = (self.index + self.size).min(len);
+    let chunk = BookShelfIterator::with_range(self.book_shelf, self.index, end);
+    self.index = end;
+    Some(chunk)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let len = (self.book_shelf.values.len() - self.index).div_ceil(self.size);
+    (len, Some(len))
+  }
+}
+
+impl ExactSizeIterator for BookShelfChunks<'_> {}
+
+impl FusedIterator for BookShelfChunks<'_> {}
+
+impl<'a> IntoIterator for &'a mut BookShelf {
+  type IntoIter = BookShelfIterMut<'a>;
+  type Item = BookMut<'a>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.iter_mut()
+  }
 }
 
 impl<'a> IntoIterator for &'a BookShelf {
@@ -87,6 +461,43 @@ impl<'a> IntoIterator for &'a BookShelf {
   }
 }
 
+pub struct BookShelfIntoIterator {
+  values: std::vec::IntoIter<Slot>,
+}
+
+impl Iterator for BookShelfIntoIterator {
+  type Item = Book;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.values.next().map(|slot| slot.book)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.values.size_hint()
+  }
+}
+
+impl DoubleEndedIterator for BookShelfIntoIterator {
+  fn next_back(&mut self) -> Option<Self::Item> {
+    self.values.next_back().map(|slot| slot.book)
+  }
+}
+
+impl ExactSizeIterator for BookShelfIntoIterator {}
+
+impl FusedIterator for BookShelfIntoIterator {}
+
+impl IntoIterator for BookShelf {
+  type IntoIter = BookShelfIntoIterator;
+  type Item = Book;
+
+  fn into_iter(self) -> Self::IntoIter {
+    BookShelfIntoIterator {
+      values: self.values.into_iter(),
+    }
+  }
+}
+
 #[cfg(test)]
 mod test {
   use super::*;
@@ -110,4 +521,79 @@ mod test {
       println!("{}", book.name())
     }
   }
+
+  #[test]
+  fn test_collection() {
+    let mut book_shelf = ["Daddy-Long-Legs", "Bible", "Cinderella"]
+      .into_iter()
+      .map(Book::new)
+      .collect::<BookShelf>();
+    assert_eq!(book_shelf.len(), 3);
+
+    let mut with_elements = BookShelf::with_elements(&[Book::new("Bible")]);
+    assert_eq!(with_elements.get_length(), 1);
+    with_elements.extend(book_shelf.iter().cloned());
+    assert_eq!(with_elements.get_length(), 4);
+
+    book_shelf.insert(0, Book::new("Around the World in 80 Days"));
+    assert_eq!(book_shelf[0].name(), "Around the World in 80 Days");
+    assert_eq!(book_shelf.remove(2), Some(Book::new("Bible")));
+    assert_eq!(book_shelf.remove(3), None);
+    assert_eq!(book_shelf.get(3), None);
+    assert_eq!(book_shelf.len(), 3);
+
+    book_shelf.sort_by_name();
+    let names = book_shelf.into_iter().map(|book| book.name).collect::<Vec<_>>();
+    assert_eq!(names, ["Around the World in 80 Days", "Cinderella", "Daddy-Long-Legs"]);
+  }
+
+  #[test]
+  fn test_double_ended() {
+    let mut book_shelf = ["A", "B", "C", "D", "E"]
+      .into_iter()
+      .map(Book::new)
+      .collect::<BookShelf>();
+
+    let mut it = book_shelf.iter();
+    assert_eq!(it.len(), 5);
+    assert_eq!(it.next().map(Book::name), Some("A"));
+    assert_eq!(it.next_back().map(Book::name), Some("E"));
+    assert_eq!(it.size_hint(), (3, Some(3)));
+    assert_eq!(it.nth(1).map(Book::name), Some("C"));
+    assert_eq!(it.nth_back(0).map(Book::name), Some("D"));
+    assert_eq!(it.next(), None);
+    assert_eq!(it.next_back(), None);
+
+    let reversed = book_shelf.iter().rev().skip(1).map(Book::name).collect::<Vec<_>>();
+    assert_eq!(reversed, ["D", "C", "B", "A"]);
+    assert_eq!(book_shelf.iter().nth(10), None);
+    assert_eq!(book_shelf.iter().skip(3).len(), 2);
+
+    for mut book in &mut book_shelf {
+      book.name.push('!');
+    }
+    if let Some(mut book) = book_shelf.iter_mut().next_back() {
+      book.name = "Z".to_owned();
+    }
+    let names = book_shelf.into_iter().rev().map(|book| book.name).collect::<Vec<_>>();
+    assert_eq!(names, ["Z", "D!", "C!", "B!", "A!"]);
+  }
+
+  #[test]
+  fn test_filter_and_chunks() {
+    let book_shelf = ["A", "B", "C", "D", "E"]
+      .into_iter()
+      .map(Book::new)
+      .collect::<BookShelf>();
+
+    let vowels = book_shelf.filter(|book| "AEIOU".contains(book.name()));
+    assert_eq!(vowels.rev().map(Book::name).collect::<Vec<_>>(), ["E", "A"]);
+
+    let chunks = book_shelf.chunks(2);
+    assert_eq!(chunks.len(), 3);
+    let chunks = chunks
+      .map(|chunk| chunk.map(Book::name).collect::<String>())
+      .collect::<Vec<_>>();
+    assert_eq!(chunks, ["AB", "CD", "E"]);
+  }
 }
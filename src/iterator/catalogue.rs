@@ -0,0 +1,365 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::value::RawValue;
+
+use super::{Book, BookShelf};
+
+const CSV_HEADER: [&str; 4] = ["name", "author", "isbn", "year"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+  pub line: u64,
+  pub reason: String,
+}
+
+impl Display for RowError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "invalid book at line {}: {}", self.line, self.reason)
+  }
+}
+
+#[derive(Debug)]
+pub enum CatalogueError {
+  Io(io::Error),
+  Syntax { line: u64, reason: String },
+  Row(RowError),
+  UnknownFormat(PathBuf),
+}
+
+impl Display for CatalogueError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CatalogueError::Io(e) => write!(f, "failed to access catalogue: {}", e),
+      CatalogueError::Syntax { line, reason } => write!(f, "malformed catalogue at line {}: {}", line, reason),
+      CatalogueError::Row(e) => write!(f, "{}", e),
+      CatalogueError::UnknownFormat(path) => write!(f, "unknown catalogue format: {}", path.display()),
+    }
+  }
+}
+
+impl std::error::Error for CatalogueError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      CatalogueError::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for CatalogueError {
+  fn from(e: io::Error) -> Self {
+    CatalogueError::Io(e)
+  }
+}
+
+impl From<csv::Error> for CatalogueError {
+  fn from(e: csv::Error) -> Self {
+    let line = e.position().map_or(0, |pos| pos.line());
+    let message = e.to_string();
+    match e.into_kind() {
+      csv::ErrorKind::Io(e) => CatalogueError::Io(e),
+      csv::ErrorKind::Deserialize { err, .. } => CatalogueError::Row(RowError {
+        line,
+        reason: err.to_string(),
+      }),
+      csv::ErrorKind::UnequalLengths { expected_len, len, .. } => CatalogueError::Row(RowError {
+        line,
+        reason: format!("expected {} fields, found {}", expected_len, len),
+      }),
+      csv::ErrorKind::Utf8 { err, .. } => CatalogueError::Row(RowError {
+        line,
+        reason: err.to_string(),
+      }),
+      csv::ErrorKind::Serialize(reason) => CatalogueError::Syntax { line, reason },
+      _ => CatalogueError::Syntax { line, reason: message },
+    }
+  }
+}
+
+impl From<serde_json::Error> for CatalogueError {
+  fn from(e: serde_json::Error) -> Self {
+    match e.io_error_kind() {
+      Some(_) => CatalogueError::Io(e.into()),
+      None => CatalogueError::Syntax {
+        line: e.line() as u64,
+        reason: json_reason(&e),
+      },
+    }
+  }
+}
+
+// serde_jsonのメッセージ末尾に付く位置情報は行番号として別に持つので取り除く
+fn json_reason(e: &serde_json::Error) -> String {
+  let message = e.to_string();
+  match message.rsplit_once(" at line ") {
+    Some((reason, _)) => reason.to_owned(),
+    None => message,
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Csv,
+  Json,
+}
+
+impl Format {
+  pub fn of_path(path: &Path) -> Result<Self, CatalogueError> {
+    match path
+      .extension()
+      .and_then(|e| e.to_str())
+      .map(str::to_ascii_lowercase)
+      .as_deref()
+    {
+      Some("csv") => Ok(Format::Csv),
+      Some("json") => Ok(Format::Json),
+      _ => Err(CatalogueError::UnknownFormat(path.to_owned())),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadMode {
+  #[default]
+  Strict,
+  Lenient,
+}
+
+#[derive(Debug)]
+pub struct Imported {
+  pub book_shelf: BookShelf,
+  pub skipped: Vec<RowError>,
+}
+
+impl Imported {
+  fn new() -> Self {
+    Self {
+      book_shelf: BookShelf::default(),
+      skipped: vec![],
+    }
+  }
+
+  fn push(&mut self, result: Result<Book, RowError>, mode: ReadMode) -> Result<(), CatalogueError> {
+    match (result, mode) {
+      (Ok(book), _) => self.book_shelf.append_book(book),
+      (Err(e), ReadMode::Lenient) => self.skipped.push(e),
+      (Err(e), ReadMode::Strict) => return Err(CatalogueError::Row(e)),
+    }
+    Ok(())
+  }
+}
+
+impl BookShelf {
+  pub fn load(path: impl AsRef<Path>, mode: ReadMode) -> Result<Imported, CatalogueError> {
+    let path = path.as_ref();
+    let format = Format::of_path(path)?;
+    Self::read(fs::File::open(path)?, format, mode)
+  }
+
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CatalogueError> {
+    let path = path.as_ref();
+    let format = Format::of_path(path)?;
+    let mut writer = io::BufWriter::new(fs::File::create(path)?);
+    self.write(&mut writer, format)?;
+    writer.flush()?;
+    Ok(())
+  }
+
+  pub fn read(reader: impl Read, format: Format, mode: ReadMode) -> Result<Imported, CatalogueError> {
+    match format {
+      Format::Csv => Self::read_csv(reader, mode),
+      Format::Json => Self::read_json(reader, mode),
+    }
+  }
+
+  pub fn write(&self, writer: impl Write, format: Format) -> Result<(), CatalogueError> {
+    match format {
+      Format::Csv => self.write_csv(writer),
+      Format::Json => self.write_json(writer),
+    }
+  }
+
+  fn read_csv(reader: impl Read, mode: ReadMode) -> Result<Imported, CatalogueError> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+    if !headers.iter().any(|h| h == "name") {
+      return Err(CatalogueError::Syntax {
+        line: 1,
+        reason: "missing column `name`".to_owned(),
+      });
+    }
+    let mut imported = Imported::new();
+    for record in reader.records() {
+      let book = record
+        .and_then(|record| record.deserialize::<Book>(Some(&headers)))
+        .map_err(CatalogueError::from);
+      let book = match book {
+        Ok(book) => Ok(book),
+        Err(CatalogueError::Row(e)) => Err(e),
+        Err(e) => return Err(e),
+      };
+      imported.push(book, mode)?;
+    }
+    Ok(imported)
+  }
+
+  fn write_csv(&self, writer: impl Write) -> Result<(), CatalogueError> {
+    // 空の本棚でもヘッダーだけは書き出す
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+    writer.write_record(CSV_HEADER)?;
+    for book in self.iter() {
+      writer.serialize(book)?;
+    }
+    writer.flush()?;
+    Ok(())
+  }
+
+  fn read_json(mut reader: impl Read, mode: ReadMode) -> Result<Imported, CatalogueError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    // 要素ごとに元のテキストを保ったまま切り出し、行番号を求められるようにする
+    let values = serde_json::from_str::<Vec<&RawValue>>(&text)?;
+    let mut imported = Imported::new();
+    for value in values {
+      let offset = value.get().as_ptr() as usize - text.as_ptr() as usize;
+      let line = text[..offset].matches('\n').count() as u64 + 1;
+      let book = serde_json::from_str::<Book>(value.get()).map_err(|e| RowError {
+        line: line + e.line().saturating_sub(1) as u64,
+        reason: json_reason(&e),
+      });
+      imported.push(book, mode)?;
+    }
+    Ok(imported)
+  }
+
+  fn write_json(&self, mut writer: impl Write) -> Result<(), CatalogueError> {
+    let books = self.iter().collect::<Vec<_>>();
+    serde_json::to_writer_pretty(&mut writer, &books)?;
+    writeln!(writer)?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn catalogue() -> BookShelf {
+    vec![
+      Book::new("Around the World in 80 Days")
+        .with_author("Jules Verne")
+        .with_isbn("978-0-14-044906-8")
+        .with_year(1872),
+      Book::new("Bible"),
+      Book::new("Cinderella, or \"The Little Glass Slipper\"")
+        .with_author("Charles Perrault")
+        .with_year(1697),
+      Book::new("Daddy-Long-Legs\nA Novel").with_isbn("978-0-14-310583-5"),
+      Book::new("").with_author(""),
+    ]
+    .into_iter()
+    .collect()
+  }
+
+  fn books(book_shelf: &BookShelf) -> Vec<Book> {
+    book_shelf.iter().cloned().collect()
+  }
+
+  #[test]
+  fn test() {
+    for format in [Format::Csv, Format::Json] {
+      let book_shelf = catalogue();
+      let mut buf = Vec::new();
+      book_shelf.write(&mut buf, format).unwrap();
+      let imported = BookShelf::read(buf.as_slice(), format, ReadMode::Strict).unwrap();
+      assert_eq!(books(&imported.book_shelf), books(&book_shelf));
+      assert!(imported.skipped.is_empty());
+
+      let mut again = Vec::new();
+      imported.book_shelf.write(&mut again, format).unwrap();
+      assert_eq!(again, buf);
+    }
+  }
+
+  #[test]
+  fn test_csv_errors() {
+    let text = "name,author,isbn,year\nBible,,,\nCinderella,Charles Perrault,,sixteen\nEmma,Jane Austen\nMomo,Michael Ende,,1973\n";
+    let e = BookShelf::read(text.as_bytes(), Format::Csv, ReadMode::Strict).unwrap_err();
+    assert!(matches!(e, CatalogueError::Row(RowError { line: 3, .. })));
+
+    let imported = BookShelf::read(text.as_bytes(), Format::Csv, ReadMode::Lenient).unwrap();
+    assert_eq!(
+      imported.book_shelf.iter().map(Book::name).collect::<Vec<_>>(),
+      ["Bible", "Momo"]
+    );
+    let lines = imported.skipped.iter().map(|e| e.line).collect::<Vec<_>>();
+    assert_eq!(lines, [3, 4]);
+    assert!(imported.skipped[0].reason.contains("invalid digit"));
+    assert_eq!(imported.skipped[1].reason, "expected 4 fields, found 2");
+
+    let e = BookShelf::read("title,year\nBible,\n".as_bytes(), Format::Csv, ReadMode::Lenient).unwrap_err();
+    assert!(matches!(e, CatalogueError::Syntax { line: 1, .. }));
+
+    // csv のエラー種別は Debug 表記ではなく、読めるメッセージとして伝える
+    let e = csv::Writer::from_writer(vec![])
+      .serialize(std::collections::BTreeMap::from([(1, 2)]))
+      .unwrap_err();
+    match CatalogueError::from(e) {
+      CatalogueError::Syntax { reason, .. } => {
+        assert!(!reason.contains("Serialize("), "{}", reason);
+        assert!(reason.contains("map"), "{}", reason);
+      }
+      e => panic!("unexpected {:?}", e),
+    }
+  }
+
+  #[test]
+  fn test_json_errors() {
+    let text = r#"[
+  {"name": "Bible"},
+  {"name": "Cinderella",
+   "year": "1697"},
+  42,
+  {"name": "Momo", "author": "Michael Ende", "year": 1973}
+]"#;
+    let e = BookShelf::read(text.as_bytes(), Format::Json, ReadMode::Strict).unwrap_err();
+    assert!(matches!(e, CatalogueError::Row(RowError { line: 4, .. })));
+
+    let imported = BookShelf::read(text.as_bytes(), Format::Json, ReadMode::Lenient).unwrap();
+    assert_eq!(imported.book_shelf.len(), 2);
+    assert_eq!(imported.book_shelf[1].year(), Some(1973));
+    let lines = imported.skipped.iter().map(|e| e.line).collect::<Vec<_>>();
+    assert_eq!(lines, [4, 5]);
+    assert_eq!(
+      imported.skipped[1].reason,
+      "invalid type: integer `42`, expected struct Book"
+    );
+
+    let e = BookShelf::read("[{\"name\": \"Bible\"},\n".as_bytes(), Format::Json, ReadMode::Lenient).unwrap_err();
+    assert!(matches!(e, CatalogueError::Syntax { line: 2, .. }));
+  }
+
+  #[test]
+  fn test_files() {
+    let dir = std::env::temp_dir().join(format!("book_shelf_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for name in ["books.csv", "books.JSON"] {
+      let path = dir.join(name);
+      catalogue().save(&path).unwrap();
+      let imported = BookShelf::load(&path, ReadMode::Strict).unwrap();
+      assert_eq!(books(&imported.book_shelf), books(&catalogue()));
+    }
+    assert!(matches!(
+      catalogue().save(dir.join("books.xml")),
+      Err(CatalogueError::UnknownFormat(_))
+    ));
+    assert!(matches!(
+      BookShelf::load(dir.join("none.csv"), ReadMode::Lenient),
+      Err(CatalogueError::Io(_))
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,149 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use super::{Book, BookShelf};
+
+// 最後に返した本のIDと、その時点での位置を保持する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cursor {
+  after: Option<u64>,
+  position: usize,
+}
+
+impl Cursor {
+  pub fn start() -> Self {
+    Self::default()
+  }
+
+  fn resolve(&self, book_shelf: &BookShelf) -> usize {
+    match self.after {
+      None => 0,
+      Some(id) => match book_shelf.positions.get(&id) {
+        Some(position) => position + 1,
+        // 本が取り除かれていた場合は、後続の本が詰められた位置から再開する
+        None => self.position.min(book_shelf.len()),
+      },
+    }
+  }
+}
+
+impl Display for Cursor {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:016x}{:016x}", self.after.map_or(0, |id| id + 1), self.position)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCursorError(String);
+
+impl Display for ParseCursorError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "invalid cursor: {:?}", self.0)
+  }
+}
+
+impl std::error::Error for ParseCursorError {}
+
+impl FromStr for Cursor {
+  type Err = ParseCursorError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let error = || ParseCursorError(s.to_owned());
+    if s.len() != 32 || !s.is_ascii() {
+      return Err(error());
+    }
+    let after = u64::from_str_radix(&s[..16], 16).map_err(|_| error())?;
+    let position = u64::from_str_radix(&s[16..], 16).map_err(|_| error())?;
+    Ok(Self {
+      after: after.checked_sub(1),
+      position: usize::try_from(position).map_err(|_| error())?,
+    })
+  }
+}
+
+#[derive(Debug)]
+pub struct Page<'a> {
+  books: Vec<&'a Book>,
+  next_cursor: Cursor,
+  has_more: bool,
+}
+
+impl<'a> Page<'a> {
+  pub fn books(&self) -> &[&'a Book] {
+    &self.books
+  }
+
+  pub fn next_cursor(&self) -> Cursor {
+    self.next_cursor
+  }
+
+  pub fn has_more(&self) -> bool {
+    self.has_more
+  }
+}
+
+impl BookShelf {
+  pub fn page(&self, cursor: &Cursor, limit: usize) -> Page<'_> {
+    let start = cursor.resolve(self);
+    let end = start.saturating_add(limit).min(self.len());
+    let next_cursor = match end > start {
+      true => Cursor {
+        after: Some(self.values[end - 1].id),
+        position: end - 1,
+      },
+      false => *cursor,
+    };
+    Page {
+      books: self.values[start..end].iter().map(|slot| &slot.book).collect(),
+      next_cursor,
+      has_more: end < self.len(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn names(page: &Page) -> Vec<String> {
+    page.books().iter().map(|book| book.name().to_owned()).collect()
+  }
+
+  #[test]
+  fn test() {
+    let mut book_shelf = ["A", "B", "C", "D", "E"]
+      .into_iter()
+      .map(Book::new)
+      .collect::<BookShelf>();
+
+    let page = book_shelf.page(&Cursor::start(), 2);
+    assert_eq!(names(&page), ["A", "B"]);
+    assert!(page.has_more());
+    let token = page.next_cursor().to_string();
+
+    book_shelf.append_book(Book::new("F"));
+    book_shelf.insert(0, Book::new("_"));
+    let cursor = token.parse::<Cursor>().unwrap();
+    let page = book_shelf.page(&cursor, 3);
+    assert_eq!(names(&page), ["C", "D", "E"]);
+
+    let cursor = page.next_cursor();
+    book_shelf.remove(5);
+    let page = book_shelf.page(&cursor, 3);
+    assert_eq!(names(&page), ["F"]);
+    assert!(!page.has_more());
+
+    let cursor = page.next_cursor();
+    assert!(book_shelf.page(&cursor, 3).books().is_empty());
+    assert_eq!(book_shelf.page(&cursor, 3).next_cursor(), cursor);
+    book_shelf.append_book(Book::new("G"));
+    assert_eq!(names(&book_shelf.page(&cursor, 3)), ["G"]);
+  }
+
+  #[test]
+  fn test_parse_error() {
+    assert!("".parse::<Cursor>().is_err());
+    assert!("zz".repeat(16).parse::<Cursor>().is_err());
+    assert_eq!(Cursor::start().to_string().parse::<Cursor>(), Ok(Cursor::start()));
+  }
+}
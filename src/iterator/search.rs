@@ -0,0 +1,357 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Bound, Deref};
+use std::sync::RwLockReadGuard;
+
+use super::{Book, BookShelf, Slot};
+
+fn normalize_isbn(isbn: &str) -> String {
+  isbn
+    .chars()
+    .filter(|c| c.is_ascii_alphanumeric())
+    .map(|c| c.to_ascii_uppercase())
+    .collect()
+}
+
+fn normalize_title(title: &str) -> String {
+  title.to_lowercase()
+}
+
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+  let mut prev = (0..=b.len()).collect::<Vec<_>>();
+  let mut curr = vec![0; b.len() + 1];
+  for (i, ca) in a.iter().enumerate() {
+    curr[0] = i + 1;
+    for (j, cb) in b.iter().enumerate() {
+      let cost = if ca == cb { 0 } else { 1 };
+      curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+    }
+    std::mem::swap(&mut prev, &mut curr);
+  }
+  prev[b.len()]
+}
+
+#[derive(Debug)]
+struct BkNode {
+  key: Vec<char>,
+  ids: Vec<u64>,
+  children: HashMap<usize, usize>,
+}
+
+// 編集距離の三角不等式を利用して、探索する部分木を絞り込むBK木
+#[derive(Debug, Default)]
+struct BkTree {
+  nodes: Vec<BkNode>,
+}
+
+impl BkTree {
+  fn insert(&mut self, key: &str, id: u64) {
+    let key = key.chars().collect::<Vec<_>>();
+    let mut index = 0;
+    if self.nodes.is_empty() {
+      self.nodes.push(BkNode {
+        key,
+        ids: vec![id],
+        children: HashMap::new(),
+      });
+      return;
+    }
+    loop {
+      let distance = edit_distance(&self.nodes[index].key, &key);
+      if distance == 0 {
+        self.nodes[index].ids.push(id);
+        return;
+      }
+      match self.nodes[index].children.get(&distance) {
+        Some(&child) => index = child,
+        None => {
+          let child = self.nodes.len();
+          self.nodes.push(BkNode {
+            key,
+            ids: vec![id],
+            children: HashMap::new(),
+          });
+          self.nodes[index].children.insert(distance, child);
+          return;
+        }
+      }
+    }
+  }
+
+  // 木の形は保ったまま、IDだけを取り除く
+  fn remove(&mut self, key: &str, id: u64) {
+    let key = key.chars().collect::<Vec<_>>();
+    let mut index = 0;
+    while let Some(node) = self.nodes.get_mut(index) {
+      let distance = edit_distance(&node.key, &key);
+      if distance == 0 {
+        node.ids.retain(|e| *e != id);
+        return;
+      }
+      match node.children.get(&distance) {
+        Some(&child) => index = child,
+        None => return,
+      }
+    }
+  }
+
+  fn search(&self, key: &str, max_distance: usize) -> Vec<(usize, &BkNode)> {
+    let key = key.chars().collect::<Vec<_>>();
+    let mut found = vec![];
+    let mut stack = match self.nodes.is_empty() {
+      true => vec![],
+      false => vec![0],
+    };
+    while let Some(index) = stack.pop() {
+      let node = &self.nodes[index];
+      let distance = edit_distance(&node.key, &key);
+      if distance <= max_distance && !node.ids.is_empty() {
+        found.push((distance, node));
+      }
+      let range = distance.saturating_sub(max_distance)..=distance + max_distance;
+      stack.extend(
+        node
+          .children
+          .iter()
+          .filter(|(d, _)| range.contains(d))
+          .map(|(_, child)| *child),
+      );
+    }
+    found.sort_by(|(d1, n1), (d2, n2)| d1.cmp(d2).then_with(|| n1.key.cmp(&n2.key)));
+    found
+  }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Indexes {
+  isbn: HashMap<String, Vec<u64>>,
+  titles: BTreeMap<String, Vec<u64>>,
+  fuzzy: BkTree,
+}
+
+impl Indexes {
+  fn build(values: &[Slot]) -> Self {
+    let mut indexes = Self::default();
+    for slot in values {
+      indexes.add(slot);
+    }
+    indexes
+  }
+
+  pub(super) fn add(&mut self, slot: &Slot) {
+    if let Some(isbn) = &slot.book.isbn {
+      self.isbn.entry(normalize_isbn(isbn)).or_default().push(slot.id);
+    }
+    let title = normalize_title(&slot.book.name);
+    self.fuzzy.insert(&title, slot.id);
+    self.titles.entry(title).or_default().push(slot.id);
+  }
+
+  pub(super) fn remove(&mut self, slot: &Slot) {
+    if let Some(isbn) = &slot.book.isbn {
+      if let Some(ids) = self.isbn.get_mut(&normalize_isbn(isbn)) {
+        ids.retain(|id| *id != slot.id);
+      }
+    }
+    let title = normalize_title(&slot.book.name);
+    self.fuzzy.remove(&title, slot.id);
+    if let Some(ids) = self.titles.get_mut(&title) {
+      ids.retain(|id| *id != slot.id);
+    }
+  }
+}
+
+// 作成済みであることを確かめた索引への読み取りロック
+struct IndexesRef<'a>(RwLockReadGuard<'a, Option<Indexes>>);
+
+impl Deref for IndexesRef<'_> {
+  type Target = Indexes;
+
+  fn deref(&self) -> &Self::Target {
+    self.0.as_ref().unwrap()
+  }
+}
+
+// 読み取りロックを保持したまま、接頭辞に一致するタイトルを一つずつたどる
+struct PrefixSearch<'a> {
+  book_shelf: &'a BookShelf,
+  indexes: IndexesRef<'a>,
+  prefix: String,
+  last: Option<String>,
+  ids: std::vec::IntoIter<u64>,
+}
+
+impl<'a> Iterator for PrefixSearch<'a> {
+  type Item = &'a Book;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      if let Some(id) = self.ids.next() {
+        return Some(self.book_shelf.book_by_id(id));
+      }
+      let lower = match &self.last {
+        Some(last) => Bound::Excluded(last.as_str()),
+        None => Bound::Included(self.prefix.as_str()),
+      };
+      let (title, ids) = self
+        .indexes
+        .titles
+        .range::<str, _>((lower, Bound::Unbounded))
+        .next()
+        .filter(|(title, _)| title.starts_with(&self.prefix))?;
+      self.ids = ids.clone().into_iter();
+      self.last = Some(title.clone());
+    }
+  }
+}
+
+impl BookShelf {
+  fn indexes(&self) -> IndexesRef<'_> {
+    let indexes = self.indexes.read().unwrap();
+    if indexes.is_some() {
+      return IndexesRef(indexes);
+    }
+    drop(indexes);
+    self
+      .indexes
+      .write()
+      .unwrap()
+      .get_or_insert_with(|| Indexes::build(&self.values));
+    IndexesRef(self.indexes.read().unwrap())
+  }
+
+  fn book_by_id(&self, id: u64) -> &Book {
+    &self.values[self.positions[&id]].book
+  }
+
+  pub fn find_by_isbn(&self, isbn: &str) -> impl Iterator<Item = &Book> + '_ {
+    let ids = self.indexes().isbn.get(&normalize_isbn(isbn)).cloned();
+    ids.into_iter().flatten().map(|id| self.book_by_id(id))
+  }
+
+  pub fn search_prefix(&self, prefix: &str) -> impl Iterator<Item = &Book> + '_ {
+    PrefixSearch {
+      book_shelf: self,
+      indexes: self.indexes(),
+      prefix: normalize_title(prefix),
+      last: None,
+      ids: Vec::new().into_iter(),
+    }
+  }
+
+  pub fn search_fuzzy(&self, title: &str, max_distance: usize) -> impl Iterator<Item = (usize, &Book)> + '_ {
+    let found = self
+      .indexes()
+      .fuzzy
+      .search(&normalize_title(title), max_distance)
+      .into_iter()
+      .flat_map(|(distance, node)| node.ids.iter().map(move |id| (distance, *id)))
+      .collect::<Vec<_>>();
+    found.into_iter().map(|(distance, id)| (distance, self.book_by_id(id)))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn catalogue() -> BookShelf {
+    vec![
+      Book::new("Around the World in 80 Days")
+        .with_author("Jules Verne")
+        .with_isbn("978-0-14-044906-8")
+        .with_year(1872),
+      Book::new("Bible"),
+      Book::new("Cinderella").with_author("Charles Perrault").with_year(1697),
+      Book::new("Daddy-Long-Legs")
+        .with_author("Jean Webster")
+        .with_isbn("978-0-14-310583-5")
+        .with_year(1912),
+      Book::new("Around Alone"),
+    ]
+    .into_iter()
+    .collect()
+  }
+
+  fn names<'a>(books: impl Iterator<Item = &'a Book>) -> Vec<&'a str> {
+    books.map(Book::name).collect()
+  }
+
+  #[test]
+  fn test() {
+    let book_shelf = catalogue();
+    assert_eq!(names(book_shelf.find_by_isbn("9780143105835")), ["Daddy-Long-Legs"]);
+    assert_eq!(book_shelf.find_by_isbn("0").count(), 0);
+    assert_eq!(
+      names(book_shelf.search_prefix("around")),
+      ["Around Alone", "Around the World in 80 Days"]
+    );
+    let fuzzy = book_shelf
+      .search_fuzzy("cinderela", 2)
+      .map(|(distance, book)| (distance, book.name()))
+      .collect::<Vec<_>>();
+    assert_eq!(fuzzy, [(1, "Cinderella")]);
+    assert_eq!(
+      names(book_shelf.search_fuzzy("bibel", 2).map(|(_, book)| book)),
+      ["Bible"]
+    );
+  }
+
+  #[test]
+  fn test_update() {
+    let mut book_shelf = catalogue();
+    assert_eq!(book_shelf.search_prefix("a").count(), 2);
+
+    book_shelf.insert(0, Book::new("Anne of Green Gables").with_isbn("0-553-21313-X"));
+    book_shelf.remove(1);
+    assert_eq!(
+      names(book_shelf.search_prefix("a")),
+      ["Anne of Green Gables", "Around Alone"]
+    );
+    assert_eq!(names(book_shelf.find_by_isbn("055321313x")), ["Anne of Green Gables"]);
+    assert_eq!(book_shelf.search_fuzzy("Around the World in 80 Days", 0).count(), 0);
+
+    book_shelf.get_mut(0).unwrap().name = "Alice in Wonderland".to_owned();
+    // 索引は作り直されず、書き換えた本の分だけ更新されている
+    assert!(book_shelf.indexes.read().unwrap().is_some());
+    assert_eq!(names(book_shelf.search_prefix("al")), ["Alice in Wonderland"]);
+    assert_eq!(book_shelf.search_prefix("anne").count(), 0);
+    assert_eq!(
+      names(book_shelf.search_fuzzy("alice in wonderlnd", 1).map(|(_, book)| book)),
+      ["Alice in Wonderland"]
+    );
+
+    for mut book in &mut book_shelf {
+      if book.name() == "Cinderella" {
+        book.isbn = Some("978-0-14-118355-2".to_owned());
+      }
+    }
+    assert!(book_shelf.indexes.read().unwrap().is_some());
+    assert_eq!(names(book_shelf.find_by_isbn("9780141183552")), ["Cinderella"]);
+  }
+
+  #[test]
+  fn test_insert_out_of_range() {
+    let mut book_shelf = catalogue();
+    assert_eq!(book_shelf.search_prefix("a").count(), 2);
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+      book_shelf.insert(10, Book::new("Anne of Green Gables"));
+    }));
+    assert!(result.is_err());
+    assert_eq!(book_shelf.len(), 5);
+    assert_eq!(
+      names(book_shelf.search_prefix("a")),
+      ["Around Alone", "Around the World in 80 Days"]
+    );
+  }
+
+  #[test]
+  fn test_many() {
+    let book_shelf = (0..20_000)
+      .map(|i| Book::new(&format!("Volume {}", i)).with_isbn(&format!("{:013}", i)))
+      .collect::<BookShelf>();
+    assert_eq!(names(book_shelf.find_by_isbn("0000000012345")), ["Volume 12345"]);
+    assert_eq!(book_shelf.search_prefix("volume 1999").count(), 11);
+    assert_eq!(book_shelf.search_fuzzy("Volume 19999", 0).count(), 1);
+  }
+}
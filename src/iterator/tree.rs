@@ -0,0 +1,191 @@
+use std::collections::VecDeque;
+use std::iter::FusedIterator;
+use std::vec;
+
+// 子を持つノードを表す。ノードは参照やRcのような安価に複製できるハンドルであることを想定している
+pub trait TreeNode: Sized {
+  fn children(&self) -> Vec<Self>;
+
+  fn preorder(self) -> Preorder<Self> {
+    Preorder { stack: vec![self] }
+  }
+
+  fn postorder(self) -> Postorder<Self> {
+    let children = self.children().into_iter();
+    Postorder {
+      stack: vec![(self, children)],
+    }
+  }
+
+  fn level_order(self) -> LevelOrder<Self> {
+    LevelOrder {
+      queue: VecDeque::from([self]),
+    }
+  }
+
+  fn leaves(self) -> Leaves<Self> {
+    Leaves { stack: vec![self] }
+  }
+}
+
+// 再帰を使わず明示的なスタックで辿るので、深い木でもスタックオーバーフローしない
+#[derive(Debug)]
+pub struct Preorder<N> {
+  stack: Vec<N>,
+}
+
+impl<N: TreeNode> Iterator for Preorder<N> {
+  type Item = N;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let node = self.stack.pop()?;
+    self.stack.extend(node.children().into_iter().rev());
+    Some(node)
+  }
+}
+
+impl<N: TreeNode> FusedIterator for Preorder<N> {}
+
+#[derive(Debug)]
+pub struct Postorder<N> {
+  stack: Vec<(N, vec::IntoIter<N>)>,
+}
+
+impl<N: TreeNode> Iterator for Postorder<N> {
+  type Item = N;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      let (_, children) = self.stack.last_mut()?;
+      match children.next() {
+        Some(child) => {
+          let grandchildren = child.children().into_iter();
+          self.stack.push((child, grandchildren));
+        }
+        None => return self.stack.pop().map(|(node, _)| node),
+      }
+    }
+  }
+}
+
+impl<N: TreeNode> FusedIterator for Postorder<N> {}
+
+#[derive(Debug)]
+pub struct LevelOrder<N> {
+  queue: VecDeque<N>,
+}
+
+impl<N: TreeNode> Iterator for LevelOrder<N> {
+  type Item = N;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let node = self.queue.pop_front()?;
+    self.queue.extend(node.children());
+    Some(node)
+  }
+}
+
+impl<N: TreeNode> FusedIterator for LevelOrder<N> {}
+
+#[derive(Debug)]
+pub struct Leaves<N> {
+  stack: Vec<N>,
+}
+
+impl<N: TreeNode> Iterator for Leaves<N> {
+  type Item = N;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while let Some(node) = self.stack.pop() {
+      let children = node.children();
+      if children.is_empty() {
+        return Some(node);
+      }
+      self.stack.extend(children.into_iter().rev());
+    }
+    None
+  }
+}
+
+impl<N: TreeNode> FusedIterator for Leaves<N> {}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[derive(Debug)]
+  struct Node {
+    name: &'static str,
+    children: Vec<Node>,
+  }
+
+  fn node(name: &'static str, children: Vec<Node>) -> Node {
+    Node { name, children }
+  }
+
+  impl TreeNode for &Node {
+    fn children(&self) -> Vec<Self> {
+      self.children.iter().collect()
+    }
+  }
+
+  // 子を必要になった時点で計算する、一本道の深い木
+  #[derive(Debug)]
+  struct Chain {
+    depth: usize,
+    max_depth: usize,
+  }
+
+  impl TreeNode for Chain {
+    fn children(&self) -> Vec<Self> {
+      match self.depth < self.max_depth {
+        true => vec![Chain {
+          depth: self.depth + 1,
+          max_depth: self.max_depth,
+        }],
+        false => vec![],
+      }
+    }
+  }
+
+  fn names<'a>(nodes: impl Iterator<Item = &'a Node>) -> Vec<&'static str> {
+    nodes.map(|n| n.name).collect()
+  }
+
+  #[test]
+  fn test() {
+    //       a
+    //     / | \
+    //    b  c  d
+    //   / \     \
+    //  e   f     g
+    let tree = node(
+      "a",
+      vec![
+        node("b", vec![node("e", vec![]), node("f", vec![])]),
+        node("c", vec![]),
+        node("d", vec![node("g", vec![])]),
+      ],
+    );
+    assert_eq!(names(tree.preorder()), ["a", "b", "e", "f", "c", "d", "g"]);
+    assert_eq!(names(tree.postorder()), ["e", "f", "b", "c", "g", "d", "a"]);
+    assert_eq!(names(tree.level_order()), ["a", "b", "c", "d", "e", "f", "g"]);
+    assert_eq!(names(tree.leaves()), ["e", "f", "c", "g"]);
+
+    let leaf = node("x", vec![]);
+    assert_eq!(names(leaf.postorder()), ["x"]);
+    assert_eq!(names(leaf.leaves()), ["x"]);
+  }
+
+  #[test]
+  fn test_deep() {
+    let root = || Chain {
+      depth: 0,
+      max_depth: 1_000_000,
+    };
+    assert_eq!(root().preorder().count(), 1_000_001);
+    assert_eq!(root().postorder().next().map(|n| n.depth), Some(1_000_000));
+    assert_eq!(root().level_order().last().map(|n| n.depth), Some(1_000_000));
+    assert_eq!(root().leaves().map(|n| n.depth).collect::<Vec<_>>(), [1_000_000]);
+  }
+}
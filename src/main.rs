@@ -1,5 +1,6 @@
 // #![feature(min_type_alias_impl_trait)]
 #![feature(type_alias_impl_trait)]
+#![feature(test)]
 // #![feature(generic_associated_types)]
 extern crate chrono;
 extern crate core;
@@ -18,6 +19,7 @@ mod iterator;
 mod mediator;
 mod observer;
 mod proxy;
+mod random;
 mod singleton;
 mod state;
 mod strategy;
@@ -1,2 +1,7 @@
+mod delivery;
 mod enum_base;
+mod event_bus;
+mod history;
+mod reactive;
+mod subscription;
 mod trait_base;
@@ -0,0 +1,141 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::Duration;
+
+use super::trait_base::Observer;
+
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+  max_attempts: u32,
+  backoff: Duration,
+  max_backoff: Duration,
+}
+
+impl RetryPolicy {
+  pub fn new(max_attempts: u32) -> Self {
+    assert!(max_attempts > 0, "at least one attempt is required");
+    Self {
+      max_attempts,
+      backoff: Duration::ZERO,
+      max_backoff: DEFAULT_MAX_BACKOFF,
+    }
+  }
+
+  pub fn none() -> Self {
+    Self::new(1)
+  }
+
+  // 再試行のたびに待ち時間を倍にする
+  pub fn with_backoff(mut self, backoff: Duration) -> Self {
+    self.backoff = backoff;
+    self
+  }
+
+  // 倍にしていく待ち時間の上限
+  pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+    self.max_backoff = max_backoff;
+    self
+  }
+
+  fn next_backoff(&self, backoff: Duration) -> Duration {
+    backoff.saturating_mul(2).min(self.max_backoff)
+  }
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self::none()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryFailure {
+  Panicked(String),
+  Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter<E> {
+  // completeの失敗ではNoneになる
+  pub event: Option<E>,
+  pub subscription: u64,
+  pub attempts: u32,
+  pub failure: DeliveryFailure,
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+  match payload.downcast::<String>() {
+    Ok(message) => *message,
+    Err(payload) => match payload.downcast::<&str>() {
+      Ok(message) => message.to_string(),
+      Err(_) => "unknown panic".to_owned(),
+    },
+  }
+}
+
+// 1つのオブザーバーへの配信を他から切り離し、パニックもエラーとして扱う
+pub(super) fn deliver<E: Clone>(
+  subscription: u64,
+  observer: &dyn Observer<E>,
+  event: &E,
+  policy: &RetryPolicy,
+) -> Result<(), DeadLetter<E>> {
+  deliver_with(subscription, event, policy, || observer.try_update(event))
+}
+
+// Observerトレイトを実装していない通知先にも、同じ再試行とデッドレターを適用する
+pub(super) fn deliver_with<E: Clone>(
+  subscription: u64,
+  event: &E,
+  policy: &RetryPolicy,
+  mut update: impl FnMut() -> anyhow::Result<()>,
+) -> Result<(), DeadLetter<E>> {
+  let mut backoff = policy.backoff.min(policy.max_backoff);
+  let mut attempt = 1;
+  loop {
+    let failure = match panic::catch_unwind(AssertUnwindSafe(&mut update)) {
+      Ok(Ok(())) => return Ok(()),
+      Ok(Err(e)) => DeliveryFailure::Failed(format!("{:#}", e)),
+      Err(payload) => DeliveryFailure::Panicked(panic_message(payload)),
+    };
+    if attempt >= policy.max_attempts {
+      return Err(DeadLetter {
+        event: Some(event.clone()),
+        subscription,
+        attempts: attempt,
+        failure,
+      });
+    }
+    thread::sleep(backoff);
+    backoff = policy.next_backoff(backoff);
+    attempt += 1;
+  }
+}
+
+// 完了の通知は再試行しないが、updateと同じく失敗を他のオブザーバーから切り離す
+pub(super) fn complete<E>(subscription: u64, observer: &dyn Observer<E>) -> Result<(), DeadLetter<E>> {
+  panic::catch_unwind(AssertUnwindSafe(|| observer.complete())).map_err(|payload| DeadLetter {
+    event: None,
+    subscription,
+    attempts: 1,
+    failure: DeliveryFailure::Panicked(panic_message(payload)),
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_backoff() {
+    let policy = RetryPolicy::new(3).with_backoff(Duration::from_secs(1));
+    assert_eq!(policy.next_backoff(Duration::from_secs(1)), Duration::from_secs(2));
+    assert_eq!(policy.next_backoff(Duration::from_secs(20)), DEFAULT_MAX_BACKOFF);
+    assert_eq!(policy.next_backoff(Duration::MAX), DEFAULT_MAX_BACKOFF);
+
+    let policy = policy.with_max_backoff(Duration::MAX);
+    assert_eq!(policy.next_backoff(Duration::MAX), Duration::MAX);
+  }
+}
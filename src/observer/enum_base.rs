@@ -1,18 +1,23 @@
-use rand::prelude::ThreadRng;
-use rand::Rng;
-use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::fmt::Debug;
+use std::ops::RangeInclusive;
 use std::rc::Rc;
-use std::{thread, time};
 
+use super::delivery::{self, DeadLetter, RetryPolicy};
+use crate::random::{self, NumberSource};
+
+#[derive(Clone)]
 pub enum NumberGenerator {
   RandomNumber(RandomNumberNumberGenerator),
 }
 
 impl NumberGenerator {
   pub fn of_random() -> Self {
-    NumberGenerator::RandomNumber(RandomNumberNumberGenerator::new())
+    Self::of_random_with(RandomNumberNumberGenerator::new())
+  }
+
+  pub fn of_random_with(generator: RandomNumberNumberGenerator) -> Self {
+    NumberGenerator::RandomNumber(generator)
   }
 
   pub fn get_number(&self) -> u32 {
@@ -22,19 +27,37 @@ impl NumberGenerator {
     }
   }
 
-  pub fn add_observer(&mut self, observer: Observer) {
+  pub fn add_observer(&self, observer: Observer) {
     match self {
       NumberGenerator::RandomNumber(g) => g.add_observer(observer),
       _ => panic!(),
     }
   }
 
+  pub fn delete_observer(&self, observer: &Observer) -> bool {
+    match self {
+      NumberGenerator::RandomNumber(g) => g.delete_observer(observer),
+    }
+  }
+
   pub fn execute(&mut self) {
     match self {
       NumberGenerator::RandomNumber(g) => g.execute(),
       _ => panic!(),
     }
   }
+
+  pub fn dead_letters(&self) -> Vec<DeadLetter<u32>> {
+    match self {
+      NumberGenerator::RandomNumber(g) => g.dead_letters(),
+    }
+  }
+
+  pub fn take_dead_letters(&self) -> Vec<DeadLetter<u32>> {
+    match self {
+      NumberGenerator::RandomNumber(g) => g.take_dead_letters(),
+    }
+  }
 }
 
 #[derive(Clone)]
@@ -42,47 +65,168 @@ pub struct RandomNumberNumberGenerator {
   inner: Rc<RefCell<RandomNumberNumberGeneratorInner>>,
 }
 
+enum SubscriptionChange {
+  Add(Observer),
+  Delete(Observer),
+}
+
 struct RandomNumberNumberGeneratorInner {
-  observers: Vec<Observer>,
-  rng: ThreadRng,
+  // デッドレターで購読を区別できるよう、登録順に振ったIDと組にして持つ
+  observers: Vec<(u64, Observer)>,
+  next_id: u64,
+  // 通知中に行われた購読の変更は、通知が終わってからまとめて反映する
+  notifying: usize,
+  pending: Vec<SubscriptionChange>,
+  rng: Box<dyn NumberSource>,
+  count: usize,
+  range: RangeInclusive<u32>,
   number: u32,
+  retry_policy: RetryPolicy,
+  dead_letters: Vec<DeadLetter<u32>>,
+}
+
+impl RandomNumberNumberGeneratorInner {
+  fn apply(&mut self, change: SubscriptionChange) -> bool {
+    match change {
+      SubscriptionChange::Add(observer) => {
+        self.observers.push((self.next_id, observer));
+        self.next_id += 1;
+        true
+      }
+      SubscriptionChange::Delete(observer) => match self.observers.iter().position(|(_, e)| *e == observer) {
+        Some(index) => {
+          self.observers.remove(index);
+          true
+        }
+        None => false,
+      },
+    }
+  }
+
+  // 予約中の変更を反映した後にも登録されているかどうか
+  fn will_contain(&self, observer: &Observer) -> bool {
+    let registered = self.observers.iter().filter(|(_, e)| e == observer).count();
+    let count = self.pending.iter().fold(registered, |count, change| match change {
+      SubscriptionChange::Add(e) if e == observer => count + 1,
+      SubscriptionChange::Delete(e) if e == observer => count.saturating_sub(1),
+      _ => count,
+    });
+    count > 0
+  }
+
+  fn change(&mut self, change: SubscriptionChange) -> bool {
+    if self.notifying == 0 {
+      return self.apply(change);
+    }
+    if let SubscriptionChange::Delete(observer) = &change {
+      if !self.will_contain(observer) {
+        return false;
+      }
+    }
+    self.pending.push(change);
+    true
+  }
+}
+
+// オブザーバーがパニックしても通知中の状態を解除し、予約された変更を反映する
+struct Notifying<'a>(&'a RefCell<RandomNumberNumberGeneratorInner>);
+
+impl<'a> Notifying<'a> {
+  fn new(inner: &'a RefCell<RandomNumberNumberGeneratorInner>) -> Self {
+    inner.borrow_mut().notifying += 1;
+    Self(inner)
+  }
+}
+
+impl Drop for Notifying<'_> {
+  fn drop(&mut self) {
+    let mut g = self.0.borrow_mut();
+    g.notifying -= 1;
+    if g.notifying == 0 {
+      for change in std::mem::take(&mut g.pending) {
+        g.apply(change);
+      }
+    }
+  }
 }
 
 impl RandomNumberNumberGenerator {
   pub fn new() -> Self {
+    Self::with_rng(rand::rng())
+  }
+
+  pub fn with_seed(seed: u64) -> Self {
+    Self::with_rng(random::seeded(seed))
+  }
+
+  pub fn with_rng(rng: impl NumberSource + 'static) -> Self {
     Self {
       inner: Rc::new(RefCell::new(RandomNumberNumberGeneratorInner {
         observers: vec![],
-        rng: rand::thread_rng(),
+        next_id: 0,
+        notifying: 0,
+        pending: vec![],
+        rng: Box::new(rng),
+        count: 20,
+        range: 0..=49,
         number: 0,
+        retry_policy: RetryPolicy::default(),
+        dead_letters: vec![],
       })),
     }
   }
 
-  fn add_observer(&mut self, observer: Observer) {
+  pub fn with_count(self, count: usize) -> Self {
+    (*self.inner).borrow_mut().count = count;
+    self
+  }
+
+  pub fn with_range(self, range: RangeInclusive<u32>) -> Self {
+    (*self.inner).borrow_mut().range = range;
+    self
+  }
+
+  pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+    (*self.inner).borrow_mut().retry_policy = retry_policy;
+    self
+  }
+
+  fn dead_letters(&self) -> Vec<DeadLetter<u32>> {
+    (*self.inner).borrow().dead_letters.clone()
+  }
+
+  fn take_dead_letters(&self) -> Vec<DeadLetter<u32>> {
+    std::mem::take(&mut (*self.inner).borrow_mut().dead_letters)
+  }
+
+  fn add_observer(&self, observer: Observer) {
     let mut g = (*self.inner).borrow_mut();
-    g.observers.push(observer);
+    g.change(SubscriptionChange::Add(observer));
   }
 
-  fn delete_observer(&mut self, observer: &Observer) {
+  // 通知中に呼ばれた場合は、登録されていれば削除を予約してtrueを返す
+  fn delete_observer(&self, observer: &Observer) -> bool {
     let mut g = (*self.inner).borrow_mut();
-    let index = g
-      .observers
-      .iter()
-      .position(|e| {
-        let p1: *const Observer = e;
-        let p2: *const Observer = observer;
-        p1 == p2
-      })
-      .unwrap();
-    g.observers.remove(index);
+    g.change(SubscriptionChange::Delete(observer.clone()))
   }
 
   fn notify_observers(&self) {
-    let g = (*self.inner).borrow();
-    for o in &g.observers {
-      let p = NumberGenerator::RandomNumber(self.clone());
-      o.update(&p);
+    let _notifying = Notifying::new(&self.inner);
+    let (observers, number, retry_policy) = {
+      let g = (*self.inner).borrow();
+      (g.observers.clone(), g.number, g.retry_policy)
+    };
+    // 借用を手放してから呼び出すので、オブザーバーはジェネレーターを操作できる
+    let p = NumberGenerator::RandomNumber(self.clone());
+    for (id, o) in &observers {
+      // パニックしたオブザーバーがいても、残りのオブザーバーには通知を続ける
+      let delivered = delivery::deliver_with(*id, &number, &retry_policy, || {
+        o.update(&p);
+        Ok(())
+      });
+      if let Err(dead_letter) = delivered {
+        (*self.inner).borrow_mut().dead_letters.push(dead_letter);
+      }
     }
   }
 
@@ -92,9 +236,11 @@ impl RandomNumberNumberGenerator {
   }
 
   fn execute(&mut self) {
-    for _ in 0..20 {
+    let count = (*self.inner).borrow().count;
+    for _ in 0..count {
       let mut g = (*self.inner).borrow_mut();
-      g.number = g.rng.gen_range(0..=49);
+      let range = g.range.clone();
+      g.number = g.rng.next_in(range);
       drop(g);
       self.notify_observers();
     }
@@ -112,12 +258,21 @@ pub enum Observer {
   Any(Rc<dyn AnyObserver>),
 }
 
+impl PartialEq for Observer {
+  fn eq(&self, other: &Self) -> bool {
+    match (self, other) {
+      (Observer::Digit, Observer::Digit) | (Observer::Graph, Observer::Graph) => true,
+      (Observer::Any(a), Observer::Any(b)) => Rc::ptr_eq(a, b),
+      _ => false,
+    }
+  }
+}
+
 impl Observer {
   pub fn update(&self, generator: &NumberGenerator) {
     match self {
       Observer::Digit => {
         println!("DigitObserver:{}", generator.get_number());
-        thread::sleep(time::Duration::from_millis(100));
       }
       Observer::Graph => {
         print!("GraphObserver:");
@@ -126,7 +281,6 @@ impl Observer {
           print!("*");
         }
         println!();
-        thread::sleep(time::Duration::from_millis(100));
       }
       Observer::Any(rc) => rc.update(generator),
     }
@@ -134,7 +288,12 @@ impl Observer {
 }
 #[cfg(test)]
 mod test {
+  use std::cell::Cell;
+  use std::rc::Weak;
+
   use super::*;
+  use crate::observer::delivery::DeliveryFailure;
+  use crate::random::Scripted;
 
   #[test]
   fn test() {
@@ -145,4 +304,178 @@ mod test {
     generator.add_observer(observer2);
     generator.execute();
   }
+
+  #[derive(Debug)]
+  struct CountingObserver {
+    count: Rc<Cell<usize>>,
+  }
+
+  impl AnyObserver for CountingObserver {
+    fn update(&self, _generator: &NumberGenerator) {
+      self.count.set(self.count.get() + 1);
+    }
+  }
+
+  // 最初の通知で別のオブザーバーを登録し、自分自身は登録を解除する
+  #[derive(Debug)]
+  struct HandOverObserver {
+    this: Weak<HandOverObserver>,
+    count: Rc<Cell<usize>>,
+    successor_count: Rc<Cell<usize>>,
+  }
+
+  impl AnyObserver for HandOverObserver {
+    fn update(&self, generator: &NumberGenerator) {
+      self.count.set(self.count.get() + 1);
+      generator.add_observer(Observer::Any(Rc::new(CountingObserver {
+        count: self.successor_count.clone(),
+      })));
+      assert!(generator.delete_observer(&Observer::Any(self.this.upgrade().unwrap())));
+    }
+  }
+
+  #[derive(Debug)]
+  struct RecordingObserver {
+    numbers: Rc<RefCell<Vec<u32>>>,
+  }
+
+  impl AnyObserver for RecordingObserver {
+    fn update(&self, generator: &NumberGenerator) {
+      self.numbers.borrow_mut().push(generator.get_number());
+    }
+  }
+
+  #[test]
+  fn test_deterministic() {
+    let record = |mut generator: NumberGenerator| {
+      let numbers = Rc::new(RefCell::new(vec![]));
+      generator.add_observer(Observer::Any(Rc::new(RecordingObserver {
+        numbers: numbers.clone(),
+      })));
+      generator.execute();
+      numbers.take()
+    };
+    let scripted = RandomNumberNumberGenerator::with_rng(Scripted::new([4, 8, 15]))
+      .with_count(5)
+      .with_range(1..=20);
+    assert_eq!(record(NumberGenerator::of_random_with(scripted)), [4, 8, 15, 4, 8]);
+
+    let seeded = || NumberGenerator::of_random_with(RandomNumberNumberGenerator::with_seed(7).with_range(1..=6));
+    let numbers = record(seeded());
+    assert_eq!(numbers.len(), 20);
+    assert!(numbers.iter().all(|n| (1..=6).contains(n)));
+    assert_eq!(numbers, record(seeded()));
+  }
+
+  #[test]
+  fn test_reentrancy() {
+    let mut generator = NumberGenerator::of_random_with(RandomNumberNumberGenerator::with_rng(Scripted::new([0])));
+    let count = Rc::new(Cell::new(0));
+    let successor_count = Rc::new(Cell::new(0));
+    let observer = Rc::new_cyclic(|this| HandOverObserver {
+      this: this.clone(),
+      count: count.clone(),
+      successor_count: successor_count.clone(),
+    });
+    generator.add_observer(Observer::Any(observer.clone()));
+    generator.execute();
+    assert_eq!(count.get(), 1);
+    assert_eq!(successor_count.get(), 19);
+
+    let digit = Observer::Digit;
+    generator.add_observer(digit.clone());
+    assert!(generator.delete_observer(&digit));
+    assert!(!generator.delete_observer(&digit));
+    assert!(!generator.delete_observer(&Observer::Any(observer)));
+  }
+
+  // 自分自身の登録解除を予約してからパニックする
+  #[derive(Debug)]
+  struct PanickingObserver {
+    this: Weak<PanickingObserver>,
+    deleted: RefCell<Vec<bool>>,
+  }
+
+  impl AnyObserver for PanickingObserver {
+    fn update(&self, generator: &NumberGenerator) {
+      let this = Observer::Any(self.this.upgrade().unwrap());
+      let mut deleted = self.deleted.borrow_mut();
+      deleted.push(generator.delete_observer(&this));
+      deleted.push(generator.delete_observer(&this));
+      deleted.push(generator.delete_observer(&Observer::Graph));
+      panic!("cannot handle {}", generator.get_number());
+    }
+  }
+
+  #[test]
+  fn test_panicking_observer() {
+    let mut generator = NumberGenerator::of_random_with(RandomNumberNumberGenerator::with_rng(Scripted::new([0])));
+    let observer = Rc::new_cyclic(|this| PanickingObserver {
+      this: this.clone(),
+      deleted: RefCell::new(vec![]),
+    });
+    generator.add_observer(Observer::Any(observer.clone()));
+    // 後ろのオブザーバーには、前のオブザーバーがパニックしても通知が届く
+    let count = Rc::new(Cell::new(0));
+    generator.add_observer(Observer::Any(Rc::new(CountingObserver { count: count.clone() })));
+    generator.execute();
+    assert_eq!(count.get(), 20);
+    // 登録されていないオブザーバーの削除は予約されない
+    assert_eq!(*observer.deleted.borrow(), [true, false, false]);
+    assert_eq!(
+      generator.take_dead_letters(),
+      [DeadLetter {
+        event: Some(0),
+        subscription: 0,
+        attempts: 1,
+        failure: DeliveryFailure::Panicked("cannot handle 0".to_owned()),
+      }]
+    );
+
+    // 予約されていた削除は通知の後で反映されている
+    generator.execute();
+    assert_eq!(count.get(), 40);
+    assert!(generator.dead_letters().is_empty());
+    assert!(!generator.delete_observer(&Observer::Any(observer)));
+  }
+
+  // 指定した回数だけパニックし、その後は通知を受け取る
+  #[derive(Debug)]
+  struct FlakyObserver {
+    failures: Cell<u32>,
+    numbers: RefCell<Vec<u32>>,
+  }
+
+  impl AnyObserver for FlakyObserver {
+    fn update(&self, generator: &NumberGenerator) {
+      if self.failures.get() > 0 {
+        self.failures.set(self.failures.get() - 1);
+        panic!("not ready");
+      }
+      self.numbers.borrow_mut().push(generator.get_number());
+    }
+  }
+
+  #[test]
+  fn test_retry() {
+    let generator = RandomNumberNumberGenerator::with_rng(Scripted::new([3]))
+      .with_count(2)
+      .with_retry_policy(RetryPolicy::new(3));
+    let mut generator = NumberGenerator::of_random_with(generator);
+    let observer = Rc::new(FlakyObserver {
+      failures: Cell::new(2),
+      numbers: RefCell::new(vec![]),
+    });
+    generator.add_observer(Observer::Any(observer.clone()));
+    generator.execute();
+    assert_eq!(*observer.numbers.borrow(), [3, 3]);
+    assert!(generator.dead_letters().is_empty());
+
+    observer.failures.set(3);
+    generator.execute();
+    assert_eq!(*observer.numbers.borrow(), [3, 3, 3]);
+    let dead_letters = generator.take_dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].attempts, 3);
+  }
 }
@@ -0,0 +1,559 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+  DropOldest,
+  Block,
+  Fail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+  Sync,
+  Queued {
+    capacity: usize,
+    backpressure: Backpressure,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublishError {
+  Full { topic: String, rejected: usize },
+}
+
+impl Display for PublishError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      PublishError::Full { topic, rejected } => {
+        write!(f, "{} subscriber(s) of {:?} had a full queue", rejected, topic)
+      }
+    }
+  }
+}
+
+impl std::error::Error for PublishError {}
+
+struct QueueState<E> {
+  events: VecDeque<E>,
+  closed: bool,
+  dropped: u64,
+}
+
+struct Queue<E> {
+  state: Mutex<QueueState<E>>,
+  not_empty: Condvar,
+  not_full: Condvar,
+  capacity: usize,
+  backpressure: Backpressure,
+}
+
+impl<E> Queue<E> {
+  fn new(capacity: usize, backpressure: Backpressure) -> Self {
+    assert!(capacity > 0, "queue capacity must be non-zero");
+    Self {
+      state: Mutex::new(QueueState {
+        events: VecDeque::with_capacity(capacity),
+        closed: false,
+        dropped: 0,
+      }),
+      not_empty: Condvar::new(),
+      not_full: Condvar::new(),
+      capacity,
+      backpressure,
+    }
+  }
+
+  // 溢れた場合にFailなら受け付けずにfalseを返す。閉じたキューへの追加は黙って捨てる
+  fn push(&self, event: E) -> bool {
+    let mut state = self.state.lock().unwrap();
+    while !state.closed && state.events.len() >= self.capacity {
+      match self.backpressure {
+        Backpressure::DropOldest => {
+          state.events.pop_front();
+          state.dropped += 1;
+        }
+        Backpressure::Block => state = self.not_full.wait(state).unwrap(),
+        Backpressure::Fail => return false,
+      }
+    }
+    if !state.closed {
+      state.events.push_back(event);
+      self.not_empty.notify_one();
+    }
+    true
+  }
+
+  fn pop(&self, deadline: Option<Instant>) -> Option<E> {
+    let mut state = self.state.lock().unwrap();
+    loop {
+      if let Some(event) = state.events.pop_front() {
+        self.not_full.notify_one();
+        return Some(event);
+      }
+      if state.closed {
+        return None;
+      }
+      state = match deadline {
+        None => self.not_empty.wait(state).unwrap(),
+        Some(deadline) => {
+          let timeout = deadline.checked_duration_since(Instant::now())?;
+          self.not_empty.wait_timeout(state, timeout).unwrap().0
+        }
+      };
+    }
+  }
+
+  fn try_pop(&self) -> Option<E> {
+    let event = self.state.lock().unwrap().events.pop_front();
+    if event.is_some() {
+      self.not_full.notify_one();
+    }
+    event
+  }
+
+  fn close(&self) {
+    self.state.lock().unwrap().closed = true;
+    self.not_empty.notify_all();
+    self.not_full.notify_all();
+  }
+
+  fn is_closed(&self) -> bool {
+    self.state.lock().unwrap().closed
+  }
+}
+
+// ワーカーがハンドラのパニックで終わっても、キューを閉じて待っている発行側を起こす
+struct CloseOnDrop<E>(Arc<Queue<E>>);
+
+impl<E> Drop for CloseOnDrop<E> {
+  fn drop(&mut self) {
+    self.0.close();
+    self.0.state.lock().unwrap_or_else(|e| e.into_inner()).events.clear();
+  }
+}
+
+type Handler<E> = Arc<dyn Fn(&E) + Send + Sync>;
+
+enum Target<E> {
+  Handler(Handler<E>),
+  Queue(Arc<Queue<E>>),
+}
+
+impl<E> Clone for Target<E> {
+  fn clone(&self) -> Self {
+    match self {
+      Target::Handler(handler) => Target::Handler(handler.clone()),
+      Target::Queue(queue) => Target::Queue(queue.clone()),
+    }
+  }
+}
+
+struct Subscriber<E> {
+  id: u64,
+  target: Target<E>,
+}
+
+struct EventBusInner<E> {
+  topics: RwLock<HashMap<String, Vec<Subscriber<E>>>>,
+  next_id: AtomicU64,
+}
+
+impl<E> EventBusInner<E> {
+  fn remove(&self, topic: &str, predicate: impl Fn(&Subscriber<E>) -> bool) {
+    let mut topics = self.topics.write().unwrap();
+    if let Some(subscribers) = topics.get_mut(topic) {
+      subscribers.retain(|s| {
+        let remove = predicate(s);
+        if let (true, Target::Queue(queue)) = (remove, &s.target) {
+          queue.close();
+        }
+        !remove
+      });
+      if subscribers.is_empty() {
+        topics.remove(topic);
+      }
+    }
+  }
+}
+
+// バスが破棄されたら、キューで待っている受信側やワーカースレッドを終わらせる
+impl<E> Drop for EventBusInner<E> {
+  fn drop(&mut self) {
+    for subscriber in self.topics.get_mut().unwrap().values().flatten() {
+      if let Target::Queue(queue) = &subscriber.target {
+        queue.close();
+      }
+    }
+  }
+}
+
+trait Detach: Send + Sync {
+  fn detach(&self, topic: &str, id: u64);
+}
+
+impl<E: Send> Detach for EventBusInner<E> {
+  fn detach(&self, topic: &str, id: u64) {
+    self.remove(topic, |s| s.id == id);
+  }
+}
+
+#[must_use = "dropping a BusSubscription removes the subscriber"]
+pub struct BusSubscription {
+  bus: Option<Weak<dyn Detach>>,
+  topic: String,
+  id: u64,
+  worker: Option<JoinHandle<()>>,
+}
+
+impl BusSubscription {
+  // キューに残っているイベントを配信し終えるまで待ってから戻る
+  pub fn unsubscribe(mut self) {
+    self.detach();
+    if let Some(worker) = self.worker.take() {
+      // ハンドラのパニックはワーカースレッドで既に報告されているので、ここでは無視する
+      let _ = worker.join();
+    }
+  }
+
+  fn detach(&mut self) {
+    if let Some(bus) = self.bus.take().and_then(|b| b.upgrade()) {
+      bus.detach(&self.topic, self.id);
+    }
+  }
+}
+
+impl Drop for BusSubscription {
+  fn drop(&mut self) {
+    self.detach();
+  }
+}
+
+impl Debug for BusSubscription {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("BusSubscription")
+      .field("topic", &self.topic)
+      .field("id", &self.id)
+      .finish()
+  }
+}
+
+pub struct EventReceiver<E> {
+  queue: Arc<Queue<E>>,
+}
+
+impl<E> EventReceiver<E> {
+  pub fn recv(&self) -> Option<E> {
+    self.queue.pop(None)
+  }
+
+  pub fn recv_timeout(&self, timeout: Duration) -> Option<E> {
+    self.queue.pop(Some(Instant::now() + timeout))
+  }
+
+  pub fn try_recv(&self) -> Option<E> {
+    self.queue.try_pop()
+  }
+
+  pub fn dropped(&self) -> u64 {
+    self.queue.state.lock().unwrap().dropped
+  }
+}
+
+impl<E> Iterator for EventReceiver<E> {
+  type Item = E;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.recv()
+  }
+}
+
+// 受信側が破棄されたキューは、次の発行時にバスから取り除かれる
+impl<E> Drop for EventReceiver<E> {
+  fn drop(&mut self) {
+    self.queue.close();
+  }
+}
+
+impl<E> Debug for EventReceiver<E> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("EventReceiver")
+      .field("capacity", &self.queue.capacity)
+      .field("backpressure", &self.queue.backpressure)
+      .finish()
+  }
+}
+
+pub struct EventBus<E> {
+  inner: Arc<EventBusInner<E>>,
+}
+
+impl<E: Clone + Send + 'static> EventBus<E> {
+  pub fn new() -> Self {
+    Self {
+      inner: Arc::new(EventBusInner {
+        topics: RwLock::new(HashMap::new()),
+        next_id: AtomicU64::new(0),
+      }),
+    }
+  }
+
+  fn add(&self, topic: &str, target: Target<E>) -> u64 {
+    let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+    let mut topics = self.inner.topics.write().unwrap();
+    topics
+      .entry(topic.to_owned())
+      .or_default()
+      .push(Subscriber { id, target });
+    id
+  }
+
+  pub fn subscribe(
+    &self,
+    topic: &str,
+    delivery: Delivery,
+    handler: impl Fn(&E) + Send + Sync + 'static,
+  ) -> BusSubscription {
+    let (target, worker) = match delivery {
+      Delivery::Sync => (Target::Handler(Arc::new(handler) as Handler<E>), None),
+      Delivery::Queued { capacity, backpressure } => {
+        let queue = Arc::new(Queue::new(capacity, backpressure));
+        let receiver = queue.clone();
+        let worker = thread::spawn(move || {
+          let receiver = CloseOnDrop(receiver);
+          while let Some(event) = receiver.0.pop(None) {
+            handler(&event);
+          }
+        });
+        (Target::Queue(queue), Some(worker))
+      }
+    };
+    let id = self.add(topic, target);
+    let bus: Arc<dyn Detach> = self.inner.clone();
+    BusSubscription {
+      bus: Some(Arc::downgrade(&bus)),
+      topic: topic.to_owned(),
+      id,
+      worker,
+    }
+  }
+
+  pub fn channel(&self, topic: &str, capacity: usize, backpressure: Backpressure) -> EventReceiver<E> {
+    let queue = Arc::new(Queue::new(capacity, backpressure));
+    self.add(topic, Target::Queue(queue.clone()));
+    EventReceiver { queue }
+  }
+
+  pub fn publish(&self, topic: &str, event: E) -> Result<(), PublishError> {
+    // 配信中はロックを持たないので、ハンドラの中から購読や発行をしてもよい
+    let targets = match self.inner.topics.read().unwrap().get(topic) {
+      Some(subscribers) => subscribers.iter().map(|s| s.target.clone()).collect::<Vec<_>>(),
+      None => return Ok(()),
+    };
+    let mut rejected = 0;
+    let mut closed = false;
+    for target in targets {
+      match target {
+        Target::Handler(handler) => handler(&event),
+        Target::Queue(queue) if queue.is_closed() => closed = true,
+        Target::Queue(queue) => {
+          if !queue.push(event.clone()) {
+            rejected += 1;
+          }
+        }
+      }
+    }
+    if closed {
+      self
+        .inner
+        .remove(topic, |s| matches!(&s.target, Target::Queue(q) if q.is_closed()));
+    }
+    match rejected {
+      0 => Ok(()),
+      rejected => Err(PublishError::Full {
+        topic: topic.to_owned(),
+        rejected,
+      }),
+    }
+  }
+
+  pub fn subscriber_count(&self, topic: &str) -> usize {
+    self.inner.topics.read().unwrap().get(topic).map_or(0, Vec::len)
+  }
+}
+
+impl<E: Clone + Send + 'static> Default for EventBus<E> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<E> Clone for EventBus<E> {
+  fn clone(&self) -> Self {
+    Self {
+      inner: self.inner.clone(),
+    }
+  }
+}
+
+impl<E> Debug for EventBus<E> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let topics = self.inner.topics.read().unwrap();
+    f.debug_struct("EventBus")
+      .field("topics", &topics.keys().collect::<Vec<_>>())
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::sync::mpsc;
+
+  use rand::RngExt;
+
+  use super::*;
+
+  fn assert_send_sync<T: Send + Sync>() {}
+
+  #[test]
+  fn test() {
+    assert_send_sync::<EventBus<String>>();
+    let bus = EventBus::new();
+    let received = Arc::new(Mutex::new(vec![]));
+    let sink = received.clone();
+    let subscription = bus.subscribe("numbers", Delivery::Sync, move |n: &u32| sink.lock().unwrap().push(*n));
+    let mut numbers = bus.channel("numbers", 32, Backpressure::Block);
+    let _letters = bus.channel("letters", 1, Backpressure::Fail);
+
+    let publisher = {
+      let bus = bus.clone();
+      thread::spawn(move || {
+        let mut rng = rand::rng();
+        for _ in 0..20 {
+          bus.publish("numbers", rng.random_range(0..=49)).unwrap();
+        }
+      })
+    };
+    let consumed = numbers.by_ref().take(20).collect::<Vec<_>>();
+    publisher.join().unwrap();
+    assert_eq!(*received.lock().unwrap(), consumed);
+    assert_eq!(bus.subscriber_count("numbers"), 2);
+    assert_eq!(bus.subscriber_count("letters"), 1);
+
+    subscription.unsubscribe();
+    bus.publish("numbers", 50).unwrap();
+    assert_eq!(bus.subscriber_count("numbers"), 1);
+    assert_eq!(received.lock().unwrap().len(), 20);
+    assert_eq!(numbers.try_recv(), Some(50));
+  }
+
+  #[test]
+  fn test_queued() {
+    let bus = EventBus::new();
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let delivery = Delivery::Queued {
+      capacity: 4,
+      backpressure: Backpressure::Block,
+    };
+    let subscription = bus.subscribe("jobs", delivery, move |job: &String| {
+      thread::sleep(Duration::from_millis(5));
+      tx.lock().unwrap().send((job.clone(), thread::current().id())).unwrap();
+    });
+    for i in 0..10 {
+      bus.publish("jobs", format!("job-{}", i)).unwrap();
+    }
+    subscription.unsubscribe();
+    let delivered = rx.iter().collect::<Vec<_>>();
+    assert_eq!(delivered.len(), 10);
+    assert_eq!(delivered[9].0, "job-9");
+    assert!(delivered.iter().all(|(_, id)| *id != thread::current().id()));
+  }
+
+  #[test]
+  fn test_backpressure() {
+    let bus = EventBus::new();
+    let latest = bus.channel("ticks", 3, Backpressure::DropOldest);
+    let strict = bus.channel("ticks", 3, Backpressure::Fail);
+    for tick in 0..5 {
+      let result = bus.publish("ticks", tick);
+      assert_eq!(result.is_err(), tick >= 3);
+    }
+    assert_eq!(
+      bus.publish("ticks", 5),
+      Err(PublishError::Full {
+        topic: "ticks".to_owned(),
+        rejected: 1,
+      })
+    );
+    assert_eq!(latest.dropped(), 3);
+    assert_eq!(std::iter::from_fn(|| latest.try_recv()).collect::<Vec<_>>(), [3, 4, 5]);
+    assert_eq!(std::iter::from_fn(|| strict.try_recv()).collect::<Vec<_>>(), [0, 1, 2]);
+    assert_eq!(strict.recv_timeout(Duration::from_millis(10)), None);
+
+    drop(strict);
+    bus.publish("ticks", 6).unwrap();
+    assert_eq!(bus.subscriber_count("ticks"), 1);
+    drop(bus);
+    assert_eq!(latest.recv(), Some(6));
+    assert_eq!(latest.recv(), None);
+  }
+
+  #[test]
+  fn test_panicking_handler() {
+    let bus = EventBus::new();
+    let (started_tx, started_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let (started_tx, release_rx) = (Mutex::new(started_tx), Mutex::new(release_rx));
+    let delivery = Delivery::Queued {
+      capacity: 1,
+      backpressure: Backpressure::Block,
+    };
+    let subscription = bus.subscribe("jobs", delivery, move |_: &u32| {
+      started_tx.lock().unwrap().send(()).unwrap();
+      release_rx.lock().unwrap().recv().unwrap();
+      panic!("handler failed");
+    });
+    bus.publish("jobs", 0).unwrap();
+    started_rx.recv().unwrap();
+    bus.publish("jobs", 1).unwrap();
+
+    // キューが満杯なので、この発行はワーカーが終わるまで待たされる
+    let (done_tx, done_rx) = mpsc::channel();
+    let publisher = {
+      let bus = bus.clone();
+      thread::spawn(move || done_tx.send(bus.publish("jobs", 2)).unwrap())
+    };
+    release_tx.send(()).unwrap();
+    assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)), Ok(Ok(())));
+    publisher.join().unwrap();
+
+    bus.publish("jobs", 3).unwrap();
+    assert_eq!(bus.subscriber_count("jobs"), 0);
+    drop(subscription);
+  }
+
+  #[test]
+  fn test_unsubscribe_after_panic() {
+    let bus = EventBus::new();
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let delivery = Delivery::Queued {
+      capacity: 4,
+      backpressure: Backpressure::Block,
+    };
+    let subscription = bus.subscribe("jobs", delivery, move |n: &u32| {
+      tx.lock().unwrap().send(*n).unwrap();
+      panic!("handler failed");
+    });
+    bus.publish("jobs", 7).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(7));
+
+    subscription.unsubscribe();
+    assert_eq!(bus.subscriber_count("jobs"), 0);
+  }
+}
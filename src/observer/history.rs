@@ -0,0 +1,347 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use std::time::Instant;
+
+use super::delivery::{self, DeadLetter, RetryPolicy};
+use super::reactive::{Clock, Observable, SystemClock};
+use super::subscription::{Subscribers, Subscription};
+use super::trait_base::Observer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+  Nothing,
+  All,
+  Last(usize),
+  Since(Instant),
+}
+
+// 発生時刻つきで直近の capacity 件だけを覚えておく
+#[derive(Debug, Clone)]
+pub struct History<E> {
+  events: VecDeque<(Instant, E)>,
+  capacity: usize,
+}
+
+impl<E: Clone> History<E> {
+  pub fn new(capacity: usize) -> Self {
+    assert!(capacity > 0, "history capacity must be non-zero");
+    Self {
+      events: VecDeque::with_capacity(capacity),
+      capacity,
+    }
+  }
+
+  pub fn record(&mut self, at: Instant, event: E) {
+    if self.events.len() == self.capacity {
+      self.events.pop_front();
+    }
+    self.events.push_back((at, event));
+  }
+
+  pub fn replay(&self, replay: Replay) -> Vec<E> {
+    let skip = match replay {
+      Replay::Nothing => self.events.len(),
+      Replay::All => 0,
+      Replay::Last(n) => self.events.len().saturating_sub(n),
+      Replay::Since(since) => self.events.partition_point(|(at, _)| *at < since),
+    };
+    self.events.iter().skip(skip).map(|(_, e)| e.clone()).collect()
+  }
+
+  pub fn last(&self) -> Option<&E> {
+    self.events.back().map(|(_, e)| e)
+  }
+
+  pub fn len(&self) -> usize {
+    self.events.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.events.is_empty()
+  }
+}
+
+pub struct Subject<E> {
+  subscribers: Subscribers<dyn Observer<E>>,
+  history: RefCell<Option<History<E>>>,
+  clock: Rc<dyn Clock>,
+  retry_policy: RetryPolicy,
+  dead_letters: RefCell<Vec<DeadLetter<E>>>,
+  completed: Cell<bool>,
+}
+
+impl<E: Clone + 'static> Subject<E> {
+  pub fn new() -> Self {
+    Self {
+      subscribers: Subscribers::new(),
+      history: RefCell::new(None),
+      clock: Rc::new(SystemClock::new()),
+      retry_policy: RetryPolicy::default(),
+      dead_letters: RefCell::new(vec![]),
+      completed: Cell::new(false),
+    }
+  }
+
+  // BehaviorSubjectのように、最新の値を1つだけ覚えておく
+  pub fn behavior(initial: E) -> Self {
+    let subject = Self::new().with_history(1);
+    subject.record(initial);
+    subject
+  }
+
+  pub fn with_history(self, capacity: usize) -> Self {
+    self.history.replace(Some(History::new(capacity)));
+    self
+  }
+
+  pub fn with_clock(mut self, clock: Rc<dyn Clock>) -> Self {
+    self.clock = clock;
+    self
+  }
+
+  pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+    self.retry_policy = retry_policy;
+    self
+  }
+
+  fn record(&self, event: E) {
+    if let Some(history) = self.history.borrow_mut().as_mut() {
+      history.record(self.clock.now(), event);
+    }
+  }
+
+  // 失敗したオブザーバーがいても、残りのオブザーバーには配信を続ける
+  fn deliver(&self, id: u64, observer: &dyn Observer<E>, event: &E) {
+    if let Err(dead_letter) = delivery::deliver(id, observer, event, &self.retry_policy) {
+      self.dead_letters.borrow_mut().push(dead_letter);
+    }
+  }
+
+  fn deliver_complete(&self, id: u64, observer: &dyn Observer<E>) {
+    if let Err(dead_letter) = delivery::complete(id, observer) {
+      self.dead_letters.borrow_mut().push(dead_letter);
+    }
+  }
+
+  // 履歴を渡し終えてから戻るので、以降の値と順序が入れ替わらない。完了済みなら続けて完了も通知する
+  pub fn subscribe(&self, observer: Box<dyn Observer<E>>, replay: Replay) -> Subscription {
+    let observer: Rc<dyn Observer<E>> = Rc::from(observer);
+    let subscription = self.subscribers.add(Rc::clone(&observer));
+    let id = subscription.ids()[0];
+    let replayed = match self.history.borrow().as_ref() {
+      Some(history) => history.replay(replay),
+      None => vec![],
+    };
+    for event in &replayed {
+      self.deliver(id, &*observer, event);
+    }
+    if self.completed.get() {
+      self.deliver_complete(id, &*observer);
+    }
+    subscription
+  }
+
+  // 完了した後の値は捨てる
+  pub fn next(&self, event: E) {
+    if self.completed.get() {
+      return;
+    }
+    self.record(event.clone());
+    for (id, o) in self.subscribers.entries() {
+      self.deliver(id, &*o, &event);
+    }
+  }
+
+  // 何度呼んでも、完了を通知するのは最初の1回だけ
+  pub fn complete(&self) {
+    if self.completed.replace(true) {
+      return;
+    }
+    for (id, o) in self.subscribers.entries() {
+      self.deliver_complete(id, &*o);
+    }
+  }
+
+  pub fn dead_letters(&self) -> Vec<DeadLetter<E>> {
+    self.dead_letters.borrow().clone()
+  }
+
+  pub fn take_dead_letters(&self) -> Vec<DeadLetter<E>> {
+    self.dead_letters.take()
+  }
+
+  pub fn current(&self) -> Option<E> {
+    self.history.borrow().as_ref().and_then(|h| h.last().cloned())
+  }
+
+  pub fn observer_count(&self) -> usize {
+    self.subscribers.len()
+  }
+
+  pub fn observable(&self) -> Observable<E> {
+    let subscribers = self.subscribers.clone();
+    Observable::new(move |observer| subscribers.add(observer)).with_clock(self.clock.clone())
+  }
+}
+
+impl<E: Clone + 'static> Default for Subject<E> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<E> Debug for Subject<E> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Subject")
+      .field("subscribers", &self.subscribers)
+      .field("history", &self.history.borrow().as_ref().map(|h| h.events.len()))
+      .field("completed", &self.completed.get())
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::Duration;
+
+  use super::super::delivery::DeliveryFailure;
+  use super::super::reactive::ManualClock;
+  use super::*;
+
+  #[derive(Debug)]
+  struct Recorder(Rc<RefCell<Vec<u32>>>);
+
+  impl Observer<u32> for Recorder {
+    fn update(&self, event: &u32) {
+      self.0.borrow_mut().push(*event);
+    }
+  }
+
+  fn recorder() -> (Box<Recorder>, Rc<RefCell<Vec<u32>>>) {
+    let values = Rc::new(RefCell::new(vec![]));
+    (Box::new(Recorder(values.clone())), values)
+  }
+
+  #[test]
+  fn test() {
+    let clock = Rc::new(ManualClock::new());
+    let subject = Subject::new().with_history(4).with_clock(clock.clone());
+    let (early, early_values) = recorder();
+    let _early = subject.subscribe(early, Replay::All);
+    for n in 1..=3 {
+      subject.next(n);
+      clock.advance(Duration::from_secs(1));
+    }
+    let since = clock.now();
+    for n in 4..=6 {
+      subject.next(n);
+      clock.advance(Duration::from_secs(1));
+    }
+
+    let (last, last_values) = recorder();
+    let _last = subject.subscribe(last, Replay::Last(2));
+    let (all, all_values) = recorder();
+    let _all = subject.subscribe(all, Replay::All);
+    let (late, late_values) = recorder();
+    let _late = subject.subscribe(late, Replay::Since(since));
+    let (live, live_values) = recorder();
+    let _live = subject.subscribe(live, Replay::Nothing);
+    subject.next(7);
+
+    assert_eq!(*early_values.borrow(), [1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(*last_values.borrow(), [5, 6, 7]);
+    assert_eq!(*all_values.borrow(), [3, 4, 5, 6, 7]);
+    assert_eq!(*late_values.borrow(), [4, 5, 6, 7]);
+    assert_eq!(*live_values.borrow(), [7]);
+    assert_eq!(subject.observer_count(), 5);
+  }
+
+  #[test]
+  fn test_behavior() {
+    let subject = Subject::behavior(0);
+    let (first, first_values) = recorder();
+    let _first = subject.subscribe(first, Replay::Last(1));
+    subject.next(42);
+    let (second, second_values) = recorder();
+    let _second = subject.subscribe(second, Replay::All);
+    assert_eq!(*first_values.borrow(), [0, 42]);
+    assert_eq!(*second_values.borrow(), [42]);
+    assert_eq!(subject.current(), Some(42));
+    assert_eq!(Subject::<u32>::new().current(), None);
+  }
+
+  // 奇数を受け取るとパニックし、完了の通知でもパニックする
+  #[derive(Debug)]
+  struct OddPanicker;
+
+  impl Observer<u32> for OddPanicker {
+    fn update(&self, event: &u32) {
+      if event % 2 == 1 {
+        panic!("odd {}", event);
+      }
+    }
+
+    fn complete(&self) {
+      panic!("cannot complete");
+    }
+  }
+
+  #[derive(Debug)]
+  struct Completions(Rc<Cell<usize>>);
+
+  impl Observer<u32> for Completions {
+    fn update(&self, _event: &u32) {}
+
+    fn complete(&self) {
+      self.0.set(self.0.get() + 1);
+    }
+  }
+
+  #[test]
+  fn test_isolation() {
+    let subject = Subject::new().with_history(3);
+    for n in 1..=3 {
+      subject.next(n);
+    }
+    // 再生中にパニックしても購読は続き、失敗は配信不能として残る
+    let panicker = subject.subscribe(Box::new(OddPanicker), Replay::All);
+    let (recorder, values) = recorder();
+    let _recorder = subject.subscribe(recorder, Replay::All);
+    subject.next(5);
+    assert_eq!(*values.borrow(), [1, 2, 3, 5]);
+    assert_eq!(subject.observer_count(), 2);
+    let id = panicker.ids()[0];
+    let dead_letters = subject.take_dead_letters();
+    assert_eq!(
+      dead_letters
+        .iter()
+        .map(|d| (d.subscription, d.event))
+        .collect::<Vec<_>>(),
+      [(id, Some(1)), (id, Some(3)), (id, Some(5))]
+    );
+    assert_eq!(dead_letters[0].failure, DeliveryFailure::Panicked("odd 1".to_owned()));
+
+    let completions = Rc::new(Cell::new(0));
+    let _completions = subject.subscribe(Box::new(Completions(completions.clone())), Replay::Nothing);
+    subject.complete();
+    subject.complete();
+    subject.next(7);
+    assert_eq!(completions.get(), 1);
+    assert_eq!(*values.borrow(), [1, 2, 3, 5]);
+    assert_eq!(
+      subject.take_dead_letters(),
+      [DeadLetter {
+        event: None,
+        subscription: id,
+        attempts: 1,
+        failure: DeliveryFailure::Panicked("cannot complete".to_owned()),
+      }]
+    );
+
+    // 完了した後に購読しても、履歴の後に完了が届く
+    let _late = subject.subscribe(Box::new(Completions(completions.clone())), Replay::All);
+    assert_eq!(completions.get(), 2);
+  }
+}
@@ -0,0 +1,578 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::subscription::Subscription;
+use super::trait_base::Observer;
+
+type Task = Box<dyn FnOnce()>;
+
+pub trait Clock: Debug {
+  fn now(&self) -> Instant;
+
+  // at を過ぎたら task を実行する。実行するのはクロックを進める側
+  fn schedule(&self, at: Instant, task: Task);
+}
+
+// 期限の早い順に取り出せるタイマーの一覧。期限が同じなら登録順に取り出す
+#[derive(Default)]
+struct Timers {
+  tasks: RefCell<Vec<(Instant, Task)>>,
+}
+
+impl Timers {
+  fn schedule(&self, at: Instant, task: Task) {
+    self.tasks.borrow_mut().push((at, task));
+  }
+
+  fn next_deadline(&self) -> Option<Instant> {
+    self.tasks.borrow().iter().map(|(at, _)| *at).min()
+  }
+
+  // 借用を手放してから実行できるよう、期限を過ぎたタスクを1つずつ取り出す
+  fn pop_due(&self, now: Instant) -> Option<(Instant, Task)> {
+    let mut tasks = self.tasks.borrow_mut();
+    let index = tasks
+      .iter()
+      .enumerate()
+      .filter(|(_, (at, _))| *at <= now)
+      .min_by_key(|(index, (at, _))| (*at, *index))
+      .map(|(index, _)| index)?;
+    Some(tasks.remove(index))
+  }
+}
+
+impl Debug for Timers {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Timers")
+      .field("pending", &self.tasks.borrow().len())
+      .finish()
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock {
+  timers: Timers,
+}
+
+impl SystemClock {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // 期限を過ぎたタイマーを実行する。イベントループから繰り返し呼び出す
+  pub fn run_due(&self) {
+    while let Some((_, task)) = self.timers.pop_due(Instant::now()) {
+      task();
+    }
+  }
+
+  // タイマーがなくなるまで、次の期限まで眠っては実行する
+  pub fn run_until_idle(&self) {
+    while let Some(at) = self.timers.next_deadline() {
+      thread::sleep(at.saturating_duration_since(Instant::now()));
+      self.run_due();
+    }
+  }
+}
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+
+  fn schedule(&self, at: Instant, task: Task) {
+    self.timers.schedule(at, task);
+  }
+}
+
+#[derive(Debug)]
+pub struct ManualClock {
+  now: Cell<Instant>,
+  timers: Timers,
+}
+
+impl ManualClock {
+  pub fn new() -> Self {
+    Self {
+      now: Cell::new(Instant::now()),
+      timers: Timers::default(),
+    }
+  }
+
+  // 途中で期限を迎えたタイマーは、その時刻まで進めてから実行する
+  pub fn advance(&self, duration: Duration) {
+    let target = self.now.get() + duration;
+    while let Some((at, task)) = self.timers.pop_due(target) {
+      self.now.set(self.now.get().max(at));
+      task();
+    }
+    self.now.set(target);
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Instant {
+    self.now.get()
+  }
+
+  fn schedule(&self, at: Instant, task: Task) {
+    self.timers.schedule(at, task);
+  }
+}
+
+// 演算子ごとに作られ、上流から受け取った値を加工して下流へ渡すオブザーバー
+struct Operator<T> {
+  name: &'static str,
+  next: Box<dyn Fn(&T)>,
+  complete: Box<dyn Fn()>,
+}
+
+impl<T> Operator<T> {
+  fn new<U: 'static>(name: &'static str, downstream: &Rc<dyn Observer<U>>, next: impl Fn(&T) + 'static) -> Self {
+    let downstream = downstream.clone();
+    Self {
+      name,
+      next: Box::new(next),
+      complete: Box::new(move || downstream.complete()),
+    }
+  }
+
+  fn on_complete(mut self, complete: impl Fn() + 'static) -> Self {
+    self.complete = Box::new(complete);
+    self
+  }
+}
+
+impl<T> Debug for Operator<T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Operator").field("name", &self.name).finish()
+  }
+}
+
+impl<T> Observer<T> for Operator<T> {
+  fn update(&self, event: &T) {
+    (self.next)(event)
+  }
+
+  fn complete(&self) {
+    (self.complete)()
+  }
+}
+
+type Subscribe<T> = dyn Fn(Rc<dyn Observer<T>>) -> Subscription;
+
+pub struct Observable<T> {
+  subscribe: Rc<Subscribe<T>>,
+  clock: Rc<dyn Clock>,
+}
+
+impl<T: 'static> Observable<T> {
+  pub fn new(subscribe: impl Fn(Rc<dyn Observer<T>>) -> Subscription + 'static) -> Self {
+    Self {
+      subscribe: Rc::new(subscribe),
+      clock: Rc::new(SystemClock::new()),
+    }
+  }
+
+  pub fn with_clock(mut self, clock: Rc<dyn Clock>) -> Self {
+    self.clock = clock;
+    self
+  }
+
+  pub fn subscribe(&self, observer: Box<dyn Observer<T>>) -> Subscription {
+    (self.subscribe)(Rc::from(observer))
+  }
+
+  // 購読のたびに演算子を作り直すので、状態は購読者ごとに独立する
+  fn lift<U: 'static>(
+    &self,
+    operator: impl Fn(Rc<dyn Observer<U>>, Rc<dyn Clock>) -> Operator<T> + 'static,
+  ) -> Observable<U> {
+    let upstream = self.subscribe.clone();
+    let clock = self.clock.clone();
+    Observable {
+      subscribe: Rc::new(move |downstream| upstream(Rc::new(operator(downstream, clock.clone())))),
+      clock: self.clock.clone(),
+    }
+  }
+
+  pub fn map<U: 'static>(&self, f: impl Fn(&T) -> U + 'static) -> Observable<U> {
+    let f = Rc::new(f);
+    self.lift(move |downstream, _| {
+      let (f, d) = (f.clone(), downstream.clone());
+      Operator::new("map", &downstream, move |e| d.update(&f(e)))
+    })
+  }
+
+  pub fn filter(&self, predicate: impl Fn(&T) -> bool + 'static) -> Observable<T> {
+    let predicate = Rc::new(predicate);
+    self.lift(move |downstream, _| {
+      let (predicate, d) = (predicate.clone(), downstream.clone());
+      Operator::new("filter", &downstream, move |e| {
+        if predicate(e) {
+          d.update(e)
+        }
+      })
+    })
+  }
+
+  pub fn throttle(&self, duration: Duration) -> Observable<T> {
+    self.lift(move |downstream, clock| {
+      let d = downstream.clone();
+      let last = Cell::new(None::<Instant>);
+      Operator::new("throttle", &downstream, move |e| {
+        let now = clock.now();
+        if last.get().is_none_or(|t| now.duration_since(t) >= duration) {
+          last.set(Some(now));
+          d.update(e);
+        }
+      })
+    })
+  }
+
+  pub fn merge(&self, other: &Observable<T>) -> Observable<T> {
+    let sources = [self.subscribe.clone(), other.subscribe.clone()];
+    Observable {
+      subscribe: Rc::new(move |downstream: Rc<dyn Observer<T>>| {
+        // 両方の上流が完了したときだけ下流を完了させる
+        let remaining = Rc::new(Cell::new(sources.len()));
+        let subscriptions = sources.iter().map(|source| {
+          let (d, complete, remaining) = (downstream.clone(), downstream.clone(), remaining.clone());
+          let operator = Operator::new("merge", &downstream, move |e| d.update(e)).on_complete(move || {
+            remaining.set(remaining.get() - 1);
+            if remaining.get() == 0 {
+              complete.complete();
+            }
+          });
+          source(Rc::new(operator))
+        });
+        subscriptions.reduce(Subscription::and).unwrap()
+      }),
+      clock: self.clock.clone(),
+    }
+  }
+}
+
+impl<T: Clone + 'static> Observable<T> {
+  pub fn buffer(&self, size: usize) -> Observable<Vec<T>> {
+    assert!(size > 0, "buffer size must be non-zero");
+    self.lift(move |downstream, _| {
+      let d = downstream.clone();
+      let buffer = Rc::new(RefCell::new(Vec::with_capacity(size)));
+      let flush = buffer.clone();
+      Operator::new("buffer", &downstream, move |e: &T| {
+        let mut buffer = buffer.borrow_mut();
+        buffer.push(e.clone());
+        if buffer.len() == size {
+          let items = std::mem::take(&mut *buffer);
+          drop(buffer);
+          d.update(&items);
+        }
+      })
+      .on_complete(move || {
+        let items = flush.take();
+        if !items.is_empty() {
+          downstream.update(&items);
+        }
+        downstream.complete();
+      })
+    })
+  }
+
+  // 最初の値が届いた時刻から duration ごとに区切って、まとめて渡す
+  pub fn window(&self, duration: Duration) -> Observable<Vec<T>> {
+    self.lift(move |downstream, clock| {
+      let d = downstream.clone();
+      let window = Rc::new(RefCell::new(None::<(Instant, Vec<T>)>));
+      let flush = window.clone();
+      Operator::new("window", &downstream, move |e: &T| {
+        let now = clock.now();
+        let closed = window
+          .borrow_mut()
+          .take_if(|(start, _)| now.duration_since(*start) >= duration);
+        if let Some((_, items)) = closed {
+          d.update(&items);
+        }
+        if window.borrow().is_none() {
+          // 次の値が届かなくても、期間が過ぎたらクロックのタイマーで区切る
+          let (window, d) = (Rc::downgrade(&window), Rc::downgrade(&d));
+          clock.schedule(
+            now + duration,
+            Box::new(move || {
+              let (Some(window), Some(d)) = (window.upgrade(), d.upgrade()) else {
+                return;
+              };
+              let closed = window.borrow_mut().take_if(|(start, _)| *start == now);
+              if let Some((_, items)) = closed {
+                d.update(&items);
+              }
+            }),
+          );
+        }
+        window
+          .borrow_mut()
+          .get_or_insert_with(|| (now, vec![]))
+          .1
+          .push(e.clone());
+      })
+      .on_complete(move || {
+        if let Some((_, items)) = flush.take() {
+          downstream.update(&items);
+        }
+        downstream.complete();
+      })
+    })
+  }
+
+  // 値が届いてから duration の間に次の値が来なければ、クロックのタイマーで渡す
+  pub fn debounce(&self, duration: Duration) -> Observable<T> {
+    self.lift(move |downstream, clock| {
+      let d = downstream.clone();
+      let pending = Rc::new(RefCell::new(None::<(Instant, T)>));
+      let flush = pending.clone();
+      Operator::new("debounce", &downstream, move |e: &T| {
+        let now = clock.now();
+        let previous = pending.borrow_mut().replace((now, e.clone()));
+        // タイマーがまだ実行されていなくても、静かな期間が過ぎていれば渡す
+        if let Some((at, value)) = previous {
+          if now.duration_since(at) >= duration {
+            d.update(&value);
+          }
+        }
+        let (pending, d) = (Rc::downgrade(&pending), Rc::downgrade(&d));
+        clock.schedule(
+          now + duration,
+          Box::new(move || {
+            let (Some(pending), Some(d)) = (pending.upgrade(), d.upgrade()) else {
+              return;
+            };
+            let quiet = pending.borrow_mut().take_if(|(at, _)| *at == now);
+            if let Some((_, value)) = quiet {
+              d.update(&value);
+            }
+          }),
+        );
+      })
+      .on_complete(move || {
+        if let Some((_, value)) = flush.take() {
+          downstream.update(&value);
+        }
+        downstream.complete();
+      })
+    })
+  }
+}
+
+impl<T: Clone + PartialEq + 'static> Observable<T> {
+  pub fn distinct_until_changed(&self) -> Observable<T> {
+    self.lift(move |downstream, _| {
+      let d = downstream.clone();
+      let last = RefCell::new(None::<T>);
+      Operator::new("distinct_until_changed", &downstream, move |e: &T| {
+        if last.borrow().as_ref() != Some(e) {
+          last.replace(Some(e.clone()));
+          d.update(e);
+        }
+      })
+    })
+  }
+}
+
+impl<T> Debug for Observable<T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Observable").field("clock", &self.clock).finish()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::super::subscription::Subscribers;
+  use super::super::trait_base::{NumberEvent, NumberGenerator, RandomNumberGenerator};
+  use super::*;
+
+  #[derive(Debug)]
+  struct Recorder<T> {
+    values: Rc<RefCell<Vec<T>>>,
+    completed: Rc<Cell<bool>>,
+  }
+
+  impl<T: Debug + Clone> Observer<T> for Recorder<T> {
+    fn update(&self, event: &T) {
+      self.values.borrow_mut().push(event.clone());
+    }
+
+    fn complete(&self) {
+      self.completed.set(true);
+    }
+  }
+
+  #[derive(Debug)]
+  struct Subject<T: 'static> {
+    subscribers: Subscribers<dyn Observer<T>>,
+  }
+
+  impl<T: Debug + Clone + 'static> Subject<T> {
+    fn new() -> Self {
+      Self {
+        subscribers: Subscribers::new(),
+      }
+    }
+
+    fn observable(&self) -> Observable<T> {
+      let subscribers = self.subscribers.clone();
+      Observable::new(move |observer| subscribers.add(observer))
+    }
+
+    fn emit(&self, value: T) {
+      for o in self.subscribers.snapshot() {
+        o.update(&value);
+      }
+    }
+
+    fn complete(&self) {
+      for o in self.subscribers.snapshot() {
+        o.complete();
+      }
+    }
+  }
+
+  fn record<T: Debug + Clone + 'static>(observable: &Observable<T>) -> (Subscription, Recorder<T>) {
+    let recorder = Recorder {
+      values: Rc::new(RefCell::new(vec![])),
+      completed: Rc::new(Cell::new(false)),
+    };
+    let subscription = observable.subscribe(Box::new(Recorder {
+      values: recorder.values.clone(),
+      completed: recorder.completed.clone(),
+    }));
+    (subscription, recorder)
+  }
+
+  #[test]
+  fn test() {
+    let subject = Subject::new();
+    let odd_tens = subject
+      .observable()
+      .distinct_until_changed()
+      .filter(|n| n % 2 == 1)
+      .map(|n| n * 10);
+    let (_subscription, recorder) = record(&odd_tens);
+    let (_subscription, batches) = record(&subject.observable().buffer(3));
+    for n in [1, 1, 2, 2, 3, 4, 4, 5] {
+      subject.emit(n);
+    }
+    assert_eq!(*recorder.values.borrow(), [10, 30, 50]);
+    assert_eq!(*batches.values.borrow(), [vec![1, 1, 2], vec![2, 3, 4]]);
+
+    subject.complete();
+    assert!(recorder.completed.get());
+    assert_eq!(batches.values.borrow()[2], [4, 5]);
+  }
+
+  #[test]
+  fn test_time() {
+    let clock = Rc::new(ManualClock::new());
+    let subject = Subject::new();
+    let observable = subject.observable().with_clock(clock.clone());
+    let (_s1, debounced) = record(&observable.debounce(Duration::from_millis(100)));
+    let (_s2, throttled) = record(&observable.throttle(Duration::from_millis(100)));
+    let (_s3, windows) = record(&observable.window(Duration::from_millis(100)));
+    for (elapsed, n) in [(0, 1), (50, 2), (70, 3), (50, 4), (30, 5), (120, 6)] {
+      clock.advance(Duration::from_millis(elapsed));
+      subject.emit(n);
+    }
+    subject.complete();
+    assert_eq!(*debounced.values.borrow(), [5, 6]);
+    assert_eq!(*throttled.values.borrow(), [1, 3, 6]);
+    assert_eq!(*windows.values.borrow(), [vec![1, 2], vec![3, 4, 5], vec![6]]);
+  }
+
+  #[test]
+  fn test_quiet_stream() {
+    let clock = Rc::new(ManualClock::new());
+    let subject = Subject::new();
+    let observable = subject.observable().with_clock(clock.clone());
+    let (_s1, debounced) = record(&observable.debounce(Duration::from_millis(100)));
+    let (_s2, windows) = record(&observable.window(Duration::from_millis(100)));
+    subject.emit(1);
+    clock.advance(Duration::from_millis(50));
+    subject.emit(2);
+    clock.advance(Duration::from_millis(99));
+    assert!(debounced.values.borrow().is_empty());
+    assert_eq!(*windows.values.borrow(), [vec![1, 2]]);
+
+    // 完了もしないし次の値も届かないが、期間が過ぎれば渡される
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(*debounced.values.borrow(), [2]);
+    subject.emit(3);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(*debounced.values.borrow(), [2, 3]);
+    assert_eq!(*windows.values.borrow(), [vec![1, 2], vec![3]]);
+    assert!(!debounced.completed.get());
+
+    // 購読を解除した後は、残っていたタイマーが実行されても何も渡さない
+    let (subscription, unsubscribed) = record(&observable.debounce(Duration::from_millis(100)));
+    subject.emit(4);
+    subscription.unsubscribe();
+    clock.advance(Duration::from_secs(1));
+    assert!(unsubscribed.values.borrow().is_empty());
+  }
+
+  #[test]
+  fn test_system_clock() {
+    let clock = Rc::new(SystemClock::new());
+    let subject = Subject::new();
+    let debounce = subject
+      .observable()
+      .with_clock(clock.clone())
+      .debounce(Duration::from_millis(50));
+    let (_subscription, debounced) = record(&debounce);
+    subject.emit(1);
+    subject.emit(2);
+    clock.run_until_idle();
+    assert_eq!(*debounced.values.borrow(), [2]);
+  }
+
+  #[test]
+  fn test_merge() {
+    let (numbers, letters) = (Subject::new(), Subject::new());
+    let merged = numbers
+      .observable()
+      .merge(&letters.observable().map(|c: &char| *c as u32));
+    let (subscription, recorder) = record(&merged);
+    numbers.emit(1);
+    letters.emit('a');
+    numbers.complete();
+    assert!(!recorder.completed.get());
+    letters.complete();
+    assert!(recorder.completed.get());
+    assert_eq!(*recorder.values.borrow(), [1, 97]);
+
+    subscription.unsubscribe();
+    assert!(numbers.subscribers.is_empty());
+    assert!(letters.subscribers.is_empty());
+  }
+
+  #[test]
+  fn test_number_generator() {
+    let mut generator = RandomNumberGenerator::new();
+    let evens = generator.observable().filter(|e| e.number % 2 == 0);
+    let (_s1, recorder) = record(&evens.map(|e: &NumberEvent| e.number));
+    let (_s2, all) = record(&generator.observable());
+    generator.execute();
+    generator.complete();
+    assert!(recorder.values.borrow().iter().all(|n| n % 2 == 0));
+    let expected = all
+      .values
+      .borrow()
+      .iter()
+      .map(|e| e.number)
+      .filter(|n| n % 2 == 0)
+      .collect::<Vec<_>>();
+    assert_eq!(*recorder.values.borrow(), expected);
+    assert!(recorder.completed.get());
+  }
+}
@@ -0,0 +1,220 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::{Debug, Formatter};
+use std::rc::{Rc, Weak};
+
+trait Detach {
+  fn detach(&self, id: u64);
+}
+
+#[must_use = "dropping a Subscription removes the observer"]
+pub struct Subscription {
+  targets: Vec<(Weak<dyn Detach>, u64)>,
+}
+
+impl Subscription {
+  pub fn unsubscribe(self) {}
+
+  // 購読を解除する手段を捨て、監視対象が生きている間は登録したままにする
+  pub fn forget(mut self) {
+    self.targets.clear();
+  }
+
+  // 購読ごとの識別子。同じ監視対象の中では重複しない
+  pub fn ids(&self) -> Vec<u64> {
+    self.targets.iter().map(|(_, id)| *id).collect()
+  }
+
+  // 複数の監視対象への購読を、まとめて解除できる1つの購読にする
+  pub fn and(mut self, mut other: Subscription) -> Subscription {
+    self.targets.append(&mut other.targets);
+    self
+  }
+}
+
+impl Drop for Subscription {
+  fn drop(&mut self) {
+    for (subscribers, id) in self.targets.drain(..) {
+      if let Some(subscribers) = subscribers.upgrade() {
+        subscribers.detach(id);
+      }
+    }
+  }
+}
+
+impl Debug for Subscription {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let ids = self.targets.iter().map(|(_, id)| id).collect::<Vec<_>>();
+    f.debug_struct("Subscription").field("ids", &ids).finish()
+  }
+}
+
+enum Slot<O: ?Sized> {
+  Strong(Rc<O>),
+  Weak(Weak<O>),
+}
+
+impl<O: ?Sized> Slot<O> {
+  fn upgrade(&self) -> Option<Rc<O>> {
+    match self {
+      Slot::Strong(observer) => Some(observer.clone()),
+      Slot::Weak(observer) => observer.upgrade(),
+    }
+  }
+
+  fn is_alive(&self) -> bool {
+    match self {
+      Slot::Strong(_) => true,
+      Slot::Weak(observer) => observer.strong_count() > 0,
+    }
+  }
+}
+
+struct SubscribersInner<O: ?Sized> {
+  entries: RefCell<Vec<(u64, Slot<O>)>>,
+  next_id: Cell<u64>,
+}
+
+impl<O: ?Sized> Detach for SubscribersInner<O> {
+  fn detach(&self, id: u64) {
+    self.entries.borrow_mut().retain(|(e, _)| *e != id);
+  }
+}
+
+pub struct Subscribers<O: ?Sized> {
+  inner: Rc<SubscribersInner<O>>,
+}
+
+impl<O: ?Sized + 'static> Subscribers<O> {
+  pub fn new() -> Self {
+    Self {
+      inner: Rc::new(SubscribersInner {
+        entries: RefCell::new(vec![]),
+        next_id: Cell::new(0),
+      }),
+    }
+  }
+
+  pub fn add(&self, observer: Rc<O>) -> Subscription {
+    self.insert(Slot::Strong(observer))
+  }
+
+  // 監視対象はオブザーバーを生かし続けず、破棄されたオブザーバーは次の通知時に取り除かれる
+  pub fn add_weak(&self, observer: Weak<O>) -> Subscription {
+    self.insert(Slot::Weak(observer))
+  }
+
+  fn insert(&self, slot: Slot<O>) -> Subscription {
+    let id = self.inner.next_id.get();
+    self.inner.next_id.set(id + 1);
+    self.inner.entries.borrow_mut().push((id, slot));
+    let inner: Rc<dyn Detach> = self.inner.clone();
+    Subscription {
+      targets: vec![(Rc::downgrade(&inner), id)],
+    }
+  }
+
+  // 通知中に購読の追加や解除ができるよう、借用を手放した複製を返す
+  pub fn snapshot(&self) -> Vec<Rc<O>> {
+    self.entries().into_iter().map(|(_, observer)| observer).collect()
+  }
+
+  // snapshotと同じだが、購読の識別子も一緒に返す
+  pub fn entries(&self) -> Vec<(u64, Rc<O>)> {
+    let mut live = vec![];
+    self
+      .inner
+      .entries
+      .borrow_mut()
+      .retain(|(id, slot)| match slot.upgrade() {
+        Some(observer) => {
+          live.push((*id, observer));
+          true
+        }
+        None => false,
+      });
+    live
+  }
+
+  pub fn len(&self) -> usize {
+    self
+      .inner
+      .entries
+      .borrow()
+      .iter()
+      .filter(|(_, slot)| slot.is_alive())
+      .count()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl<O: ?Sized + 'static> Default for Subscribers<O> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<O: ?Sized> Clone for Subscribers<O> {
+  fn clone(&self) -> Self {
+    Self {
+      inner: self.inner.clone(),
+    }
+  }
+}
+
+impl<O: ?Sized> Debug for Subscribers<O> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Subscribers")
+      .field("entries", &self.inner.entries.borrow().len())
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test() {
+    let subscribers = Subscribers::<str>::new();
+    let a = subscribers.add(Rc::from("a"));
+    let b = subscribers.add(Rc::from("b"));
+    subscribers.add(Rc::from("c")).forget();
+    assert_eq!(subscribers.len(), 3);
+
+    a.unsubscribe();
+    drop(b);
+    let names = subscribers.snapshot();
+    assert_eq!(names.iter().map(|s| &**s).collect::<Vec<_>>(), ["c"]);
+    assert_eq!(subscribers.entries()[0].0, 2);
+
+    let others = Subscribers::<str>::new();
+    let both = subscribers.add(Rc::from("d")).and(others.add(Rc::from("e")));
+    assert_eq!(both.ids(), [3, 0]);
+    assert_eq!((subscribers.len(), others.len()), (2, 1));
+    drop(both);
+    assert_eq!((subscribers.len(), others.len()), (1, 0));
+
+    let f = subscribers.add(Rc::from("f"));
+    drop(subscribers);
+    drop(f);
+  }
+
+  #[test]
+  fn test_weak() {
+    let subscribers = Subscribers::<str>::new();
+    let a = Rc::<str>::from("a");
+    let b = Rc::<str>::from("b");
+    subscribers.add_weak(Rc::downgrade(&a)).forget();
+    let _b = subscribers.add_weak(Rc::downgrade(&b));
+    assert_eq!(subscribers.len(), 2);
+
+    drop(a);
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers.inner.entries.borrow().len(), 2);
+    assert_eq!(subscribers.snapshot(), std::slice::from_ref(&b));
+    assert_eq!(subscribers.inner.entries.borrow().len(), 1);
+  }
+}
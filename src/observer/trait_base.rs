@@ -1,55 +1,159 @@
+use std::cell::RefCell;
 use std::fmt::Debug;
-use std::{thread, time};
+use std::ops::RangeInclusive;
+use std::rc::{Rc, Weak};
 
 use rand::prelude::ThreadRng;
-use rand::Rng;
+use rand::rngs::StdRng;
+
+use super::delivery::{self, DeadLetter, RetryPolicy};
+use super::history::{History, Replay};
+use super::reactive::{Clock, Observable, SystemClock};
+use super::subscription::{Subscribers, Subscription};
+use crate::random::{self, NumberSource};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberEvent {
+  pub index: usize,
+  pub number: u32,
+}
 
 pub trait NumberGenerator {
-  fn add_observer(&mut self, observer: Box<dyn Observer>);
-  fn delete_observer(&mut self, observer: Box<dyn Observer>);
+  fn add_observer(&mut self, observer: Box<dyn Observer<NumberEvent>>) -> Subscription;
+  fn add_weak_observer(&mut self, observer: Weak<dyn Observer<NumberEvent>>) -> Subscription;
+  fn observer_count(&self) -> usize;
   fn notify_observers(&self);
   fn get_number(&self) -> u32;
   fn execute(&mut self);
+  fn complete(&mut self);
 }
 
 #[derive(Debug)]
-pub struct RandomNumberGenerator {
-  observers: Vec<Box<dyn Observer>>,
-  rng: ThreadRng,
+pub struct RandomNumberGenerator<R = ThreadRng> {
+  observers: Subscribers<dyn Observer<NumberEvent>>,
+  rng: R,
+  count: usize,
+  range: RangeInclusive<u32>,
+  index: usize,
   number: u32,
+  retry_policy: RetryPolicy,
+  dead_letters: RefCell<Vec<DeadLetter<NumberEvent>>>,
+  history: Option<History<NumberEvent>>,
+  clock: Rc<dyn Clock>,
+  completed: bool,
 }
 
 impl RandomNumberGenerator {
   pub fn new() -> Self {
+    Self::with_rng(rand::rng())
+  }
+}
+
+impl RandomNumberGenerator<StdRng> {
+  pub fn with_seed(seed: u64) -> Self {
+    Self::with_rng(random::seeded(seed))
+  }
+}
+
+impl<R: NumberSource> RandomNumberGenerator<R> {
+  pub fn with_rng(rng: R) -> Self {
     Self {
-      observers: Vec::new(),
-      rng: rand::thread_rng(),
+      observers: Subscribers::new(),
+      rng,
+      count: 20,
+      range: 0..=49,
+      index: 0,
       number: 0,
+      retry_policy: RetryPolicy::default(),
+      dead_letters: RefCell::new(vec![]),
+      history: None,
+      clock: Rc::new(SystemClock::new()),
+      completed: false,
+    }
+  }
+
+  pub fn with_count(mut self, count: usize) -> Self {
+    self.count = count;
+    self
+  }
+
+  pub fn with_range(mut self, range: RangeInclusive<u32>) -> Self {
+    self.range = range;
+    self
+  }
+
+  pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+    self.retry_policy = retry_policy;
+    self
+  }
+
+  // 後から購読したオブザーバーへ再生できるよう、直近の capacity 件を覚えておく
+  pub fn with_history(mut self, capacity: usize) -> Self {
+    self.history = Some(History::new(capacity));
+    self
+  }
+
+  pub fn with_clock(mut self, clock: Rc<dyn Clock>) -> Self {
+    self.clock = clock;
+    self
+  }
+
+  pub fn history(&self) -> Vec<NumberEvent> {
+    self.history.as_ref().map(|h| h.replay(Replay::All)).unwrap_or_default()
+  }
+
+  // 履歴を再生し終えてから戻るので、以降の通知と順序が入れ替わらない
+  pub fn add_observer_replaying(&mut self, observer: Box<dyn Observer<NumberEvent>>, replay: Replay) -> Subscription {
+    let observer = Rc::from(observer);
+    let subscription = self.observers.add(Rc::clone(&observer));
+    if let Some(history) = &self.history {
+      let id = subscription.ids()[0];
+      for event in history.replay(replay) {
+        if let Err(dead_letter) = delivery::deliver(id, &*observer, &event, &self.retry_policy) {
+          self.dead_letters.borrow_mut().push(dead_letter);
+        }
+      }
     }
+    subscription
+  }
+
+  pub fn dead_letters(&self) -> Vec<DeadLetter<NumberEvent>> {
+    self.dead_letters.borrow().clone()
+  }
+
+  pub fn take_dead_letters(&self) -> Vec<DeadLetter<NumberEvent>> {
+    self.dead_letters.take()
+  }
+
+  pub fn observable(&self) -> Observable<NumberEvent> {
+    let observers = self.observers.clone();
+    Observable::new(move |observer| observers.add(observer))
   }
 }
 
-impl NumberGenerator for RandomNumberGenerator {
-  fn add_observer(&mut self, observer: Box<dyn Observer>) {
-    self.observers.push(observer);
+impl<R: NumberSource> NumberGenerator for RandomNumberGenerator<R> {
+  fn add_observer(&mut self, observer: Box<dyn Observer<NumberEvent>>) -> Subscription {
+    self.observers.add(Rc::from(observer))
   }
 
-  fn delete_observer(&mut self, observer: Box<dyn Observer>) {
-    let index = self
-      .observers
-      .iter()
-      .position(|e| {
-        let p1: *const dyn Observer = &**e;
-        let p2: *const dyn Observer = &*observer;
-        std::ptr::addr_eq(p1, p2)
-      })
-      .unwrap();
-    self.observers.remove(index);
+  fn add_weak_observer(&mut self, observer: Weak<dyn Observer<NumberEvent>>) -> Subscription {
+    self.observers.add_weak(observer)
+  }
+
+  fn observer_count(&self) -> usize {
+    self.observers.len()
   }
 
   fn notify_observers(&self) {
-    for o in &self.observers {
-      o.update(self)
+    let event = NumberEvent {
+      index: self.index,
+      number: self.number,
+    };
+    // 失敗したオブザーバーがいても、残りのオブザーバーには配信を続ける
+    for (id, o) in self.observers.entries() {
+      if let Err(dead_letter) = delivery::deliver(id, &*o, &event, &self.retry_policy) {
+        self.dead_letters.borrow_mut().push(dead_letter);
+      }
     }
   }
 
@@ -57,16 +161,48 @@ impl NumberGenerator for RandomNumberGenerator {
     self.number
   }
 
+  // 完了した後は何も生成しない
   fn execute(&mut self) {
-    for _ in 0..20 {
-      self.number = self.rng.gen_range(0..=49);
+    if self.completed {
+      return;
+    }
+    for _ in 0..self.count {
+      self.number = self.rng.next_in(self.range.clone());
+      if let Some(history) = &mut self.history {
+        let event = NumberEvent {
+          index: self.index,
+          number: self.number,
+        };
+        history.record(self.clock.now(), event);
+      }
       self.notify_observers();
+      self.index += 1;
+    }
+  }
+
+  // 何度呼んでも、完了を通知するのは最初の1回だけ
+  fn complete(&mut self) {
+    if std::mem::replace(&mut self.completed, true) {
+      return;
+    }
+    for (id, o) in self.observers.entries() {
+      if let Err(dead_letter) = delivery::complete(id, &*o) {
+        self.dead_letters.borrow_mut().push(dead_letter);
+      }
     }
   }
 }
 
-pub trait Observer: Debug {
-  fn update(&self, generator: &dyn NumberGenerator);
+pub trait Observer<E>: Debug {
+  fn update(&self, event: &E);
+
+  // 失敗し得るオブザーバーはこちらを実装し、エラーを通知元へ返す
+  fn try_update(&self, event: &E) -> anyhow::Result<()> {
+    self.update(event);
+    Ok(())
+  }
+
+  fn complete(&self) {}
 }
 
 #[derive(Debug)]
@@ -78,10 +214,9 @@ impl DigitObserver {
   }
 }
 
-impl Observer for DigitObserver {
-  fn update(&self, generator: &dyn NumberGenerator) {
-    println!("DigitObserver:{}", generator.get_number());
-    thread::sleep(time::Duration::from_millis(100));
+impl Observer<NumberEvent> for DigitObserver {
+  fn update(&self, event: &NumberEvent) {
+    println!("DigitObserver:{}", event.number);
   }
 }
 
@@ -94,29 +229,330 @@ impl GraphObserver {
   }
 }
 
-impl Observer for GraphObserver {
-  fn update(&self, generator: &dyn NumberGenerator) {
+impl Observer<NumberEvent> for GraphObserver {
+  fn update(&self, event: &NumberEvent) {
     print!("GraphObserver:");
-    let count = generator.get_number();
+    let count = event.number;
     for _ in 0..count {
       print!("*");
     }
     println!();
-    thread::sleep(time::Duration::from_millis(100));
   }
 }
 
 #[cfg(test)]
 mod test {
+  use std::cell::Cell;
+  use std::time::Duration;
+
+  use super::super::reactive::ManualClock;
+  use super::delivery::DeliveryFailure;
   use super::*;
+  use crate::random::Scripted;
+
+  #[derive(Debug, Default)]
+  struct RecordingObserver {
+    events: Rc<RefCell<Vec<NumberEvent>>>,
+    subscription: RefCell<Option<Subscription>>,
+    limit: Option<usize>,
+  }
+
+  impl Observer<NumberEvent> for RecordingObserver {
+    fn update(&self, event: &NumberEvent) {
+      let mut events = self.events.borrow_mut();
+      events.push(*event);
+      // 上限に達したら通知の最中に自分自身の購読を解除する
+      if Some(events.len()) == self.limit {
+        self.subscription.borrow_mut().take();
+      }
+    }
+  }
 
   #[test]
   fn test() {
     let mut generator = RandomNumberGenerator::new();
     let observer1 = DigitObserver::new();
     let observer2 = GraphObserver::new();
-    generator.add_observer(Box::new(observer1));
-    generator.add_observer(Box::new(observer2));
+    let _subscription1 = generator.add_observer(Box::new(observer1));
+    let _subscription2 = generator.add_observer(Box::new(observer2));
+    generator.execute();
+  }
+
+  #[test]
+  fn test_deterministic() {
+    let record = |mut generator: RandomNumberGenerator<_>| {
+      let events = Rc::new(RefCell::new(vec![]));
+      let _subscription = generator.add_observer(Box::new(RecordingObserver {
+        events: events.clone(),
+        ..RecordingObserver::default()
+      }));
+      generator.execute();
+      let numbers = events.borrow().iter().map(|e| e.number).collect::<Vec<_>>();
+      numbers
+    };
+    let seeded = record(RandomNumberGenerator::with_seed(7).with_count(50).with_range(1..=6));
+    assert_eq!(seeded.len(), 50);
+    assert!(seeded.iter().all(|n| (1..=6).contains(n)));
+    assert_eq!(
+      seeded,
+      record(RandomNumberGenerator::with_seed(7).with_count(50).with_range(1..=6))
+    );
+
+    let mut generator = RandomNumberGenerator::with_rng(Scripted::new([4, 8, 15, 16, 23, 42])).with_count(3);
+    let events = Rc::new(RefCell::new(vec![]));
+    let _subscription = generator.add_observer(Box::new(RecordingObserver {
+      events: events.clone(),
+      ..RecordingObserver::default()
+    }));
+    generator.execute();
+    generator.execute();
+    let expected = [4, 8, 15, 16, 23, 42]
+      .iter()
+      .enumerate()
+      .map(|(index, number)| NumberEvent { index, number: *number })
+      .collect::<Vec<_>>();
+    assert_eq!(*events.borrow(), expected);
+  }
+
+  #[test]
+  fn test_subscription() {
+    let mut generator = RandomNumberGenerator::new();
+    let events = Rc::new(RefCell::new(vec![]));
+    let subscription = generator.add_observer(Box::new(RecordingObserver {
+      events: events.clone(),
+      ..RecordingObserver::default()
+    }));
+    generator.execute();
+    assert_eq!(events.borrow().len(), 20);
+    assert_eq!(events.borrow()[19].index, 19);
+    assert!(events.borrow().iter().all(|e| e.number <= 49));
+    assert_eq!(events.borrow().last().unwrap().number, generator.get_number());
+
+    subscription.unsubscribe();
+    generator.execute();
+    assert_eq!(events.borrow().len(), 20);
+
+    {
+      let _subscription = generator.add_observer(Box::new(RecordingObserver {
+        events: events.clone(),
+        ..RecordingObserver::default()
+      }));
+      generator.notify_observers();
+    }
+    generator.notify_observers();
+    assert_eq!(events.borrow().len(), 21);
+  }
+
+  #[test]
+  fn test_unsubscribe_during_update() {
+    let mut generator = RandomNumberGenerator::new();
+    let events = Rc::new(RefCell::new(vec![]));
+    let observer = Rc::new(RecordingObserver {
+      events: events.clone(),
+      limit: Some(3),
+      ..RecordingObserver::default()
+    });
+    let subscription = generator.add_observer(Box::new(SharedObserver(observer.clone())));
+    *observer.subscription.borrow_mut() = Some(subscription);
+    generator.execute();
+    assert_eq!(events.borrow().len(), 3);
+  }
+
+  #[derive(Debug)]
+  struct SharedObserver(Rc<RecordingObserver>);
+
+  impl Observer<NumberEvent> for SharedObserver {
+    fn update(&self, event: &NumberEvent) {
+      self.0.update(event)
+    }
+  }
+
+  #[test]
+  fn test_weak_observer() {
+    let mut generator = RandomNumberGenerator::with_seed(1);
+    let events = Rc::new(RefCell::new(vec![]));
+    let widget = Rc::new(RecordingObserver {
+      events: events.clone(),
+      ..RecordingObserver::default()
+    });
+    let weak = Rc::downgrade(&widget);
+    generator.add_weak_observer(weak).forget();
+    let _subscription = generator.add_observer(Box::new(GraphObserver::new()));
+    assert_eq!(generator.observer_count(), 2);
+    generator.execute();
+    assert_eq!(events.borrow().len(), 20);
+
+    // 破棄されたオブザーバーは数えられず、次の通知で登録も取り除かれる
+    drop(widget);
+    assert_eq!(generator.observer_count(), 1);
+    generator.execute();
+    assert_eq!(events.borrow().len(), 20);
+    assert_eq!(generator.observer_count(), 1);
+  }
+
+  #[test]
+  fn test_replay() {
+    let clock = Rc::new(ManualClock::new());
+    let mut generator = RandomNumberGenerator::with_rng(Scripted::new([3, 1, 4, 1, 5, 9]))
+      .with_count(6)
+      .with_history(4)
+      .with_clock(clock.clone());
+    let start = clock.now();
+    generator.execute();
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(generator.history().len(), 4);
+
+    let replay = |generator: &mut RandomNumberGenerator<_>, replay: Replay| {
+      let events = Rc::new(RefCell::new(vec![]));
+      let subscription = generator.add_observer_replaying(
+        Box::new(RecordingObserver {
+          events: events.clone(),
+          ..RecordingObserver::default()
+        }),
+        replay,
+      );
+      (subscription, events)
+    };
+    // 遅れて接続したダッシュボードも、直近の値をすぐに受け取れる
+    let (_s1, latest) = replay(&mut generator, Replay::Last(1));
+    assert_eq!(*latest.borrow(), [NumberEvent { index: 5, number: 9 }]);
+    let (_s2, since) = replay(&mut generator, Replay::Since(start));
+    let numbers = since.borrow().iter().map(|e| e.number).collect::<Vec<_>>();
+    assert_eq!(numbers, [4, 1, 5, 9]);
+    let (_s3, after) = replay(&mut generator, Replay::Since(clock.now()));
+    assert!(after.borrow().is_empty());
+
+    generator.execute();
+    assert_eq!(latest.borrow().len(), 7);
+    assert_eq!(latest.borrow()[1], NumberEvent { index: 6, number: 3 });
+    assert_eq!(after.borrow().len(), 6);
+
+    let mut generator = RandomNumberGenerator::with_rng(Scripted::new([2])).with_count(3);
+    generator.execute();
+    let (_s4, nothing) = replay(&mut generator, Replay::All);
+    assert!(nothing.borrow().is_empty());
+    assert!(generator.history().is_empty());
+  }
+
+  #[derive(Debug)]
+  struct PanickingObserver;
+
+  impl Observer<NumberEvent> for PanickingObserver {
+    fn update(&self, event: &NumberEvent) {
+      panic!("cannot handle {}", event.number);
+    }
+  }
+
+  #[derive(Debug, Default)]
+  struct FlakyObserver {
+    attempts: Cell<(usize, u32)>,
+  }
+
+  impl Observer<NumberEvent> for FlakyObserver {
+    fn update(&self, _event: &NumberEvent) {}
+
+    fn try_update(&self, event: &NumberEvent) -> anyhow::Result<()> {
+      let attempts = match self.attempts.get() {
+        (index, attempts) if index == event.index => attempts + 1,
+        _ => 1,
+      };
+      self.attempts.set((event.index, attempts));
+      // 偶数番目のイベントは3回目で、奇数番目のイベントは一度も成功しない
+      match (event.index % 2, attempts) {
+        (0, 3) => Ok(()),
+        _ => anyhow::bail!("connection reset"),
+      }
+    }
+  }
+
+  #[test]
+  fn test_error_isolation() {
+    let mut generator = RandomNumberGenerator::new().with_retry_policy(RetryPolicy::new(3));
+    let events = Rc::new(RefCell::new(vec![]));
+    let panicking = generator.add_observer(Box::new(PanickingObserver));
+    let flaky = generator.add_observer(Box::new(FlakyObserver::default()));
+    let _subscription3 = generator.add_observer(Box::new(RecordingObserver {
+      events: events.clone(),
+      ..RecordingObserver::default()
+    }));
+    generator.execute();
+    assert_eq!(events.borrow().len(), 20);
+
+    let dead_letters = generator.take_dead_letters();
+    assert_eq!(dead_letters.len(), 30);
+    let panicked = dead_letters
+      .iter()
+      .filter(|d| panicking.ids() == [d.subscription])
+      .collect::<Vec<_>>();
+    assert_eq!(panicked.len(), 20);
+    assert_eq!(panicked[0].attempts, 3);
+    assert_eq!(
+      panicked[0].failure,
+      DeliveryFailure::Panicked(format!("cannot handle {}", panicked[0].event.unwrap().number))
+    );
+    let failed = dead_letters
+      .iter()
+      .filter(|d| flaky.ids() == [d.subscription])
+      .collect::<Vec<_>>();
+    assert!(failed.iter().all(|d| d.event.unwrap().index % 2 == 1));
+    assert!(failed
+      .iter()
+      .all(|d| d.failure == DeliveryFailure::Failed("connection reset".to_owned())));
+    assert!(generator.dead_letters().is_empty());
+  }
+
+  #[derive(Debug)]
+  struct CompletingObserver {
+    completions: Rc<Cell<usize>>,
+    panics: bool,
+  }
+
+  impl Observer<NumberEvent> for CompletingObserver {
+    fn update(&self, _event: &NumberEvent) {}
+
+    fn complete(&self) {
+      if self.panics {
+        panic!("cannot complete");
+      }
+      self.completions.set(self.completions.get() + 1);
+    }
+  }
+
+  #[test]
+  fn test_complete() {
+    let mut generator = RandomNumberGenerator::with_rng(Scripted::new([1, 2])).with_count(2);
+    let completions = Rc::new(Cell::new(0));
+    let events = Rc::new(RefCell::new(vec![]));
+    let panicking = generator.add_observer(Box::new(CompletingObserver {
+      completions: completions.clone(),
+      panics: true,
+    }));
+    let _completing = generator.add_observer(Box::new(CompletingObserver {
+      completions: completions.clone(),
+      panics: false,
+    }));
+    let _recording = generator.add_observer(Box::new(RecordingObserver {
+      events: events.clone(),
+      ..RecordingObserver::default()
+    }));
+    generator.execute();
+    generator.execute();
+    assert_eq!(completions.get(), 0);
+
+    // 完了の通知でパニックしても、残りのオブザーバーには完了が届く
+    generator.complete();
+    generator.complete();
+    assert_eq!(completions.get(), 1);
+    assert_eq!(
+      generator.take_dead_letters(),
+      [DeadLetter {
+        event: None,
+        subscription: panicking.ids()[0],
+        attempts: 1,
+        failure: DeliveryFailure::Panicked("cannot complete".to_owned()),
+      }]
+    );
     generator.execute();
+    assert_eq!(events.borrow().len(), 4);
   }
 }
@@ -0,0 +1,73 @@
+use std::fmt::Debug;
+use std::ops::RangeInclusive;
+
+use rand::rngs::StdRng;
+use rand::{Rng, RngExt, SeedableRng};
+
+// 乱数生成器を差し替えられるようにするための、範囲内の整数を1つずつ返す源
+pub trait NumberSource: Debug {
+  fn next_in(&mut self, range: RangeInclusive<u32>) -> u32;
+}
+
+impl<R: Rng + Debug> NumberSource for R {
+  fn next_in(&mut self, range: RangeInclusive<u32>) -> u32 {
+    self.random_range(range)
+  }
+}
+
+pub fn seeded(seed: u64) -> StdRng {
+  StdRng::seed_from_u64(seed)
+}
+
+// 決められた数列を繰り返し返す。テストで結果を固定するために使う
+#[derive(Debug, Clone)]
+pub struct Scripted {
+  values: Vec<u32>,
+  position: usize,
+}
+
+impl Scripted {
+  pub fn new(values: impl IntoIterator<Item = u32>) -> Self {
+    let values = values.into_iter().collect::<Vec<_>>();
+    assert!(!values.is_empty(), "a script needs at least one value");
+    Self { values, position: 0 }
+  }
+}
+
+impl NumberSource for Scripted {
+  fn next_in(&mut self, range: RangeInclusive<u32>) -> u32 {
+    let value = self.values[self.position];
+    self.position = (self.position + 1) % self.values.len();
+    assert!(
+      range.contains(&value),
+      "scripted value {} is outside {:?}",
+      value,
+      range
+    );
+    value
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test() {
+    let mut scripted = Scripted::new([3, 1, 4]);
+    let values = (0..5).map(|_| scripted.next_in(0..=9)).collect::<Vec<_>>();
+    assert_eq!(values, [3, 1, 4, 3, 1]);
+
+    let (mut a, mut b) = (seeded(42), seeded(42));
+    let a = (0..10).map(|_| a.next_in(0..=49)).collect::<Vec<_>>();
+    let b = (0..10).map(|_| b.next_in(0..=49)).collect::<Vec<_>>();
+    assert_eq!(a, b);
+    assert!(a.iter().all(|n| *n <= 49));
+  }
+
+  #[test]
+  #[should_panic(expected = "outside")]
+  fn test_out_of_range() {
+    Scripted::new([7]).next_in(0..=2);
+  }
+}
@@ -1,2 +1,4 @@
+mod diagram;
 mod enum_base;
+mod machine;
 mod trait_base;
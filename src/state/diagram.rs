@@ -0,0 +1,249 @@
+use std::fmt::{Debug, Write};
+
+use super::machine::{Event, StateMachine, Transition};
+
+// ガードは常に成り立ち得るものとして、遷移表だけから判定する
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reachability<S> {
+  pub unreachable: Vec<S>,
+  pub dead_ends: Vec<S>,
+}
+
+impl<S> Reachability<S> {
+  pub fn is_ok(&self) -> bool {
+    self.unreachable.is_empty() && self.dead_ends.is_empty()
+  }
+}
+
+fn name<S: Debug>(state: &S) -> String {
+  format!("{:?}", state)
+}
+
+// Mermaid の区切りになる文字は実体参照に置き換え、改行は <br/> にする
+fn mermaid_text(s: &str) -> String {
+  let mut text = String::new();
+  for c in s.chars() {
+    match c {
+      '#' => text.push_str("#35;"),
+      ':' => text.push_str("#58;"),
+      ';' => text.push_str("#59;"),
+      '"' => text.push_str("#quot;"),
+      '<' => text.push_str("#lt;"),
+      '>' => text.push_str("#gt;"),
+      '\n' => text.push_str("<br/>"),
+      '\r' => {}
+      c => text.push(c),
+    }
+  }
+  text
+}
+
+fn label<S, E: Event, C>(transition: &Transition<S, E, C>) -> String {
+  match transition.guard() {
+    Some(guard) => format!("{:?} [{}]", transition.event(), guard),
+    None => format!("{:?}", transition.event()),
+  }
+}
+
+fn quote(s: &str) -> String {
+  format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl<S: Clone + PartialEq + Debug, E: Event, C> StateMachine<S, E, C> {
+  // 内部遷移は状態を変えないので、破線の自己ループとして描く
+  pub fn to_dot(&self) -> String {
+    let mut dot = String::from("digraph {\n  rankdir=LR;\n  __start [shape=point];\n");
+    for state in self.states() {
+      let shape = if self.is_final(state) { "doublecircle" } else { "box" };
+      writeln!(dot, "  {} [shape={}];", quote(&name(state)), shape).unwrap();
+    }
+    writeln!(dot, "  __start -> {};", quote(&name(self.initial()))).unwrap();
+    for t in self.transitions() {
+      let from = quote(&name(t.from()));
+      match t.to() {
+        Some(to) => writeln!(dot, "  {} -> {} [label={}];", from, quote(&name(to)), quote(&label(t))),
+        None => writeln!(
+          dot,
+          "  {} -> {} [label={}, style=dashed];",
+          from,
+          from,
+          quote(&label(t))
+        ),
+      }
+      .unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+  }
+
+  // 状態名は衝突し得るので、状態には順番に s0, s1, ... という識別子を振る
+  pub fn to_mermaid(&self) -> String {
+    let states = self.states();
+    let id = |state: &S| format!("s{}", states.iter().position(|s| *s == state).unwrap());
+    let mut mermaid = String::from("stateDiagram-v2\n");
+    for state in &states {
+      writeln!(mermaid, "  state \"{}\" as {}", mermaid_text(&name(*state)), id(state)).unwrap();
+    }
+    writeln!(mermaid, "  [*] --> {}", id(self.initial())).unwrap();
+    for t in self.transitions() {
+      let to = t.to().unwrap_or(t.from());
+      writeln!(
+        mermaid,
+        "  {} --> {} : {}",
+        id(t.from()),
+        id(to),
+        mermaid_text(&label(t))
+      )
+      .unwrap();
+    }
+    for state in states.iter().filter(|s| self.is_final(s)) {
+      writeln!(mermaid, "  {} --> [*]", id(state)).unwrap();
+    }
+    mermaid
+  }
+
+  // 初期状態から辿れない状態と、辿れるのに出ていく遷移がない状態を報告する
+  pub fn check_reachability(&self) -> Reachability<S> {
+    let mut reached = vec![self.initial()];
+    let mut frontier = vec![self.initial()];
+    while let Some(state) = frontier.pop() {
+      for to in self
+        .transitions()
+        .iter()
+        .filter(|t| t.from() == state)
+        .filter_map(|t| t.to())
+      {
+        if !reached.contains(&to) {
+          reached.push(to);
+          frontier.push(to);
+        }
+      }
+    }
+    let states = self.states();
+    let unreachable = states
+      .iter()
+      .filter(|s| !reached.contains(s))
+      .map(|s| (*s).clone())
+      .collect();
+    let dead_ends = states
+      .iter()
+      .filter(|s| reached.contains(s) && !self.is_final(s))
+      .filter(|s| !self.transitions().iter().any(|t| t.from() == **s && t.to().is_some()))
+      .map(|s| (*s).clone())
+      .collect();
+    Reachability { unreachable, dead_ends }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[derive(Debug, Clone, Copy, PartialEq)]
+  enum Order {
+    Cart,
+    Paid,
+    Shipped,
+    Delivered,
+    Refunded,
+    Archived,
+  }
+
+  #[derive(Debug, Clone, Copy, PartialEq)]
+  enum OrderEvent {
+    Pay,
+    Ship,
+    Deliver,
+    Refund,
+    Remind,
+  }
+
+  impl Event for OrderEvent {
+    type Kind = OrderEvent;
+
+    fn kind(&self) -> OrderEvent {
+      *self
+    }
+  }
+
+  fn order() -> StateMachine<Order, OrderEvent, ()> {
+    StateMachine::builder()
+      .final_state(Order::Delivered)
+      .state(Order::Archived)
+      .transitions([
+        Transition::new(Order::Cart, OrderEvent::Pay, Order::Paid).with_guard("在庫あり", |_, _| true),
+        Transition::internal(Order::Cart, OrderEvent::Remind),
+        Transition::new(Order::Paid, OrderEvent::Ship, Order::Shipped),
+        Transition::new(Order::Paid, OrderEvent::Refund, Order::Refunded),
+        Transition::new(Order::Shipped, OrderEvent::Deliver, Order::Delivered),
+      ])
+      .build(Order::Cart)
+  }
+
+  #[test]
+  fn test() {
+    let machine = order();
+    assert_eq!(
+      machine.to_mermaid(),
+      "stateDiagram-v2
+  state \"Cart\" as s0
+  state \"Delivered\" as s1
+  state \"Archived\" as s2
+  state \"Paid\" as s3
+  state \"Shipped\" as s4
+  state \"Refunded\" as s5
+  [*] --> s0
+  s0 --> s3 : Pay [在庫あり]
+  s0 --> s0 : Remind
+  s3 --> s4 : Ship
+  s3 --> s5 : Refund
+  s4 --> s1 : Deliver
+  s1 --> [*]
+"
+    );
+    let dot = machine.to_dot();
+    assert!(dot.starts_with("digraph {\n"));
+    assert!(dot.contains("  \"Delivered\" [shape=doublecircle];\n"));
+    assert!(dot.contains("  __start -> \"Cart\";\n"));
+    assert!(dot.contains("  \"Cart\" -> \"Paid\" [label=\"Pay [在庫あり]\"];\n"));
+    assert!(dot.contains("  \"Cart\" -> \"Cart\" [label=\"Remind\", style=dashed];\n"));
+    assert!(dot.ends_with("}\n"));
+  }
+
+  #[test]
+  fn test_reachability() {
+    let reachability = order().check_reachability();
+    assert_eq!(
+      reachability,
+      Reachability {
+        unreachable: vec![Order::Archived],
+        dead_ends: vec![Order::Refunded],
+      }
+    );
+    assert!(!reachability.is_ok());
+  }
+
+  #[test]
+  fn test_escape() {
+    #[derive(Debug, Clone, PartialEq)]
+    struct Mode(&'static str);
+
+    let machine: StateMachine<Mode, OrderEvent, ()> = StateMachine::builder()
+      .transition(Transition::new(Mode("a\"b"), OrderEvent::Pay, Mode("c d")))
+      .final_state(Mode("c d"))
+      .build(Mode("a\"b"));
+    assert!(machine.to_dot().contains(r#""Mode(\"a\\\"b\")" -> "Mode(\"c d\")""#));
+    let mermaid = machine.to_mermaid();
+    assert!(mermaid.contains("  state \"Mode(#quot;a\\#quot;b#quot;)\" as s0\n"));
+    assert!(mermaid.contains("  state \"Mode(#quot;c d#quot;)\" as s1\n"));
+    assert!(machine.check_reachability().is_ok());
+
+    // 名前を置き換えると同じになる状態も、別の状態として描く
+    let machine: StateMachine<Mode, OrderEvent, ()> = StateMachine::builder()
+      .transition(Transition::new(Mode("a b"), OrderEvent::Pay, Mode("a_b")).with_guard("x: 1;\ny", |_, _| true))
+      .build(Mode("a b"));
+    let mermaid = machine.to_mermaid();
+    assert!(mermaid.contains("  s0 --> s1 : Pay [x#58; 1#59;<br/>y]\n"));
+    assert_eq!(mermaid.lines().filter(|l| l.starts_with("  state ")).count(), 2);
+  }
+}
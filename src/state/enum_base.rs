@@ -1,9 +1,14 @@
 use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use super::machine::{self, StateMachine, Transition, TransitionError};
+
+const DAYTIME: Range<u32> = 9..17;
 
 pub trait Context {
   fn change_state(&mut self, state: State);
-  fn call_security_center(&self, msg: &str);
-  fn record_log(&self, msg: &str);
+  fn call_security_center(&mut self, msg: &str);
+  fn record_log(&mut self, msg: &str);
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -21,70 +26,93 @@ impl Display for State {
   }
 }
 
-impl State {
-  pub fn do_clock(self, context: &mut impl Context, hour: u32) -> State {
-    let new_state = match (self, (9..17).contains(&hour)) {
-      (State::Night, true) | (State::Day, false) => self.opposite(),
-      _ => self,
-    };
-    if new_state != self {
-      context.change_state(new_state);
-    }
-    context.record_log(&format!("時刻は{}時になりました。", hour));
-    context.record_log(&format!("現在の状態は{}です。", new_state));
-    new_state
-  }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+  Clock(u32),
+  Use,
+  Alarm,
+  Phone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+  Clock,
+  Use,
+  Alarm,
+  Phone,
+}
+
+impl machine::Event for Event {
+  type Kind = EventKind;
 
-  pub fn do_use(self, context: &impl Context) {
+  fn kind(&self) -> EventKind {
     match self {
-      State::Night => context.call_security_center("非常：夜間の金庫使用！"),
-      State::Day => context.record_log("金庫使用(昼間)"),
+      Event::Clock(_) => EventKind::Clock,
+      Event::Use => EventKind::Use,
+      Event::Alarm => EventKind::Alarm,
+      Event::Phone => EventKind::Phone,
     }
   }
+}
 
-  pub fn do_alarm(self, context: &impl Context) {
-    let msg = match self {
-      State::Night => "非常ベル(夜間)",
-      State::Day => "非常ベル(昼間)",
-    };
-    context.call_security_center(msg);
-  }
+fn is_daytime(event: &Event) -> bool {
+  matches!(event, Event::Clock(hour) if DAYTIME.contains(hour))
+}
 
-  pub fn do_phone(self, context: &impl Context) {
-    let msg = match self {
-      State::Night => "夜間の通話録音",
-      State::Day => "通常の通話(昼間)",
-    };
-    context.record_log(msg);
-  }
+fn log<C: Context>(msg: &'static str) -> impl Fn(&mut C, &Event) {
+  move |context, _| context.record_log(msg)
+}
 
-  fn opposite(self) -> Self {
-    match self {
-      State::Day => State::Night,
-      State::Night => State::Day,
-    }
+fn call<C: Context>(msg: &'static str) -> impl Fn(&mut C, &Event) {
+  move |context, _| context.call_security_center(msg)
+}
+
+impl State {
+  pub fn machine<C: Context + 'static>(self) -> StateMachine<State, Event, C> {
+    StateMachine::builder()
+      .transitions([
+        Transition::new(State::Day, EventKind::Clock, State::Night).with_guard("夜間", |_, e| !is_daytime(e)),
+        Transition::new(State::Night, EventKind::Clock, State::Day).with_guard("昼間", |_, e| is_daytime(e)),
+        Transition::internal(State::Day, EventKind::Clock),
+        Transition::internal(State::Night, EventKind::Clock),
+        Transition::internal(State::Day, EventKind::Use).with_action(log("金庫使用(昼間)")),
+        Transition::internal(State::Night, EventKind::Use).with_action(call("非常：夜間の金庫使用！")),
+        Transition::internal(State::Day, EventKind::Alarm).with_action(call("非常ベル(昼間)")),
+        Transition::internal(State::Night, EventKind::Alarm).with_action(call("非常ベル(夜間)")),
+        Transition::internal(State::Day, EventKind::Phone).with_action(log("通常の通話(昼間)")),
+        Transition::internal(State::Night, EventKind::Phone).with_action(log("夜間の通話録音")),
+      ])
+      .on_entry(State::Day, |context: &mut C| context.change_state(State::Day))
+      .on_entry(State::Night, |context: &mut C| context.change_state(State::Night))
+      .build(self)
   }
 }
 
 struct StateContext {
   state: State,
+  logs: Vec<String>,
 }
 
 impl StateContext {
   fn new(state: State) -> Self {
-    Self { state }
+    Self { state, logs: vec![] }
   }
 
-  fn run(&mut self) {
+  fn run(&mut self) -> Result<(), TransitionError<State, EventKind>> {
+    let mut machine = self.state.machine();
     for hour in 0..=24 {
-      self.state = self.state.do_clock(self, hour);
-      match hour % 3 {
-        0 => self.state.do_use(self),
-        1 => self.state.do_alarm(self),
-        2 => self.state.do_phone(self),
+      let new_state = *machine.fire(self, &Event::Clock(hour))?;
+      self.record_log(&format!("時刻は{}時になりました。", hour));
+      self.record_log(&format!("現在の状態は{}です。", new_state));
+      let event = match hour % 3 {
+        0 => Event::Use,
+        1 => Event::Alarm,
+        2 => Event::Phone,
         _ => unreachable!(),
-      }
+      };
+      machine.fire(self, &event)?;
     }
+    Ok(())
   }
 }
 
@@ -93,12 +121,16 @@ impl Context for StateContext {
     self.state = state;
   }
 
-  fn call_security_center(&self, msg: &str) {
-    println!("{}:{}", self.state, msg);
+  fn call_security_center(&mut self, msg: &str) {
+    let line = format!("{}:{}", self.state, msg);
+    println!("{}", line);
+    self.logs.push(line);
   }
 
-  fn record_log(&self, msg: &str) {
-    println!("{}:{}", self.state, msg);
+  fn record_log(&mut self, msg: &str) {
+    let line = format!("{}:{}", self.state, msg);
+    println!("{}", line);
+    self.logs.push(line);
   }
 }
 
@@ -109,6 +141,38 @@ mod test {
   #[test]
   fn test() {
     let mut context = StateContext::new(State::Day);
-    context.run();
+    context.run().unwrap();
+    assert_eq!(context.state, State::Night);
+    assert_eq!(context.logs.len(), 25 * 3);
+    assert_eq!(
+      context.logs[..3],
+      [
+        "[夜間]:時刻は0時になりました。",
+        "[夜間]:現在の状態は[夜間]です。",
+        "[夜間]:非常：夜間の金庫使用！"
+      ]
+    );
+    assert_eq!(
+      context.logs[27..30],
+      [
+        "[昼間]:時刻は9時になりました。",
+        "[昼間]:現在の状態は[昼間]です。",
+        "[昼間]:金庫使用(昼間)"
+      ]
+    );
+  }
+
+  #[test]
+  fn test_diagram() {
+    let machine = State::Day.machine::<StateContext>();
+    let mermaid = machine.to_mermaid();
+    assert!(mermaid.starts_with(
+      "stateDiagram-v2\n  state \"Day\" as s0\n  state \"Night\" as s1\n  [*] --> s0\n  s0 --> s1 : Clock [夜間]\n"
+    ));
+    assert!(mermaid.contains("  s1 --> s1 : Use\n"));
+    assert!(machine
+      .to_dot()
+      .contains("  \"Night\" -> \"Day\" [label=\"Clock [昼間]\"];\n"));
+    assert!(machine.check_reachability().is_ok());
   }
 }
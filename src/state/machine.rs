@@ -0,0 +1,401 @@
+use std::fmt::{Debug, Display, Formatter};
+
+// 遷移表はイベントそのものではなく、イベントの種類で引く
+pub trait Event {
+  type Kind: Copy + PartialEq + Debug;
+  fn kind(&self) -> Self::Kind;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransitionError<S, K> {
+  Unhandled { state: S, event: K },
+  Rejected { state: S, event: K },
+}
+
+impl<S: Debug, K: Debug> Display for TransitionError<S, K> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      TransitionError::Unhandled { state, event } => write!(f, "no transition from {:?} on {:?}", state, event),
+      TransitionError::Rejected { state, event } => {
+        write!(f, "every guard rejected {:?} in {:?}", event, state)
+      }
+    }
+  }
+}
+
+impl<S: Debug, K: Debug> std::error::Error for TransitionError<S, K> {}
+
+type Hook<C> = Box<dyn Fn(&mut C)>;
+type Predicate<C, E> = Box<dyn Fn(&C, &E) -> bool>;
+type Action<C, E> = Box<dyn Fn(&mut C, &E)>;
+
+struct Guard<C, E> {
+  label: String,
+  predicate: Predicate<C, E>,
+}
+
+pub struct Transition<S, E: Event, C> {
+  from: S,
+  event: E::Kind,
+  // None の場合は内部遷移で、状態も出入りのフックも変わらない
+  to: Option<S>,
+  guard: Option<Guard<C, E>>,
+  action: Option<Action<C, E>>,
+}
+
+impl<S, E: Event, C> Transition<S, E, C> {
+  pub fn new(from: S, event: E::Kind, to: S) -> Self {
+    Self {
+      from,
+      event,
+      to: Some(to),
+      guard: None,
+      action: None,
+    }
+  }
+
+  pub fn internal(state: S, event: E::Kind) -> Self {
+    Self {
+      from: state,
+      event,
+      to: None,
+      guard: None,
+      action: None,
+    }
+  }
+
+  pub fn with_guard(mut self, label: &str, predicate: impl Fn(&C, &E) -> bool + 'static) -> Self {
+    self.guard = Some(Guard {
+      label: label.to_owned(),
+      predicate: Box::new(predicate),
+    });
+    self
+  }
+
+  pub fn with_action(mut self, action: impl Fn(&mut C, &E) + 'static) -> Self {
+    self.action = Some(Box::new(action));
+    self
+  }
+
+  pub fn from(&self) -> &S {
+    &self.from
+  }
+
+  pub fn event(&self) -> E::Kind {
+    self.event
+  }
+
+  pub fn to(&self) -> Option<&S> {
+    self.to.as_ref()
+  }
+
+  pub fn guard(&self) -> Option<&str> {
+    self.guard.as_ref().map(|g| g.label.as_str())
+  }
+}
+
+impl<S: Debug, E: Event, C> Debug for Transition<S, E, C> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Transition")
+      .field("from", &self.from)
+      .field("event", &self.event)
+      .field("to", &self.to)
+      .field("guard", &self.guard())
+      .finish()
+  }
+}
+
+pub struct StateMachineBuilder<S, E: Event, C> {
+  states: Vec<S>,
+  finals: Vec<S>,
+  transitions: Vec<Transition<S, E, C>>,
+  entries: Vec<(S, Hook<C>)>,
+  exits: Vec<(S, Hook<C>)>,
+}
+
+impl<S: Clone + PartialEq + Debug, E: Event, C> StateMachineBuilder<S, E, C> {
+  pub fn new() -> Self {
+    Self {
+      states: vec![],
+      finals: vec![],
+      transitions: vec![],
+      entries: vec![],
+      exits: vec![],
+    }
+  }
+
+  // 遷移を持たない状態も、図や到達可能性の検査に含めたい場合は宣言しておく
+  pub fn state(mut self, state: S) -> Self {
+    self.states.push(state);
+    self
+  }
+
+  // 終了状態は出ていく遷移がなくても行き止まりとはみなさない
+  pub fn final_state(mut self, state: S) -> Self {
+    self.finals.push(state.clone());
+    self.state(state)
+  }
+
+  // 同じ状態とイベントに複数の遷移がある場合は、宣言順にガードを評価する
+  pub fn transition(mut self, transition: Transition<S, E, C>) -> Self {
+    self.transitions.push(transition);
+    self
+  }
+
+  pub fn transitions(mut self, transitions: impl IntoIterator<Item = Transition<S, E, C>>) -> Self {
+    self.transitions.extend(transitions);
+    self
+  }
+
+  pub fn on_entry(mut self, state: S, hook: impl Fn(&mut C) + 'static) -> Self {
+    self.entries.push((state, Box::new(hook)));
+    self
+  }
+
+  pub fn on_exit(mut self, state: S, hook: impl Fn(&mut C) + 'static) -> Self {
+    self.exits.push((state, Box::new(hook)));
+    self
+  }
+
+  pub fn build(self, initial: S) -> StateMachine<S, E, C> {
+    StateMachine {
+      initial: initial.clone(),
+      state: initial,
+      states: self.states,
+      finals: self.finals,
+      transitions: self.transitions,
+      entries: self.entries,
+      exits: self.exits,
+    }
+  }
+}
+
+impl<S: Clone + PartialEq + Debug, E: Event, C> Default for StateMachineBuilder<S, E, C> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+pub struct StateMachine<S, E: Event, C> {
+  initial: S,
+  state: S,
+  states: Vec<S>,
+  finals: Vec<S>,
+  transitions: Vec<Transition<S, E, C>>,
+  entries: Vec<(S, Hook<C>)>,
+  exits: Vec<(S, Hook<C>)>,
+}
+
+impl<S: Clone + PartialEq + Debug, E: Event, C> StateMachine<S, E, C> {
+  pub fn builder() -> StateMachineBuilder<S, E, C> {
+    StateMachineBuilder::new()
+  }
+
+  pub fn state(&self) -> &S {
+    &self.state
+  }
+
+  pub fn initial(&self) -> &S {
+    &self.initial
+  }
+
+  // 初期状態、宣言された状態、遷移に現れる状態の順に重複なく返す
+  pub fn states(&self) -> Vec<&S> {
+    let mut states: Vec<&S> = vec![];
+    let candidates = std::iter::once(&self.initial).chain(&self.states).chain(
+      self
+        .transitions
+        .iter()
+        .flat_map(|t| std::iter::once(&t.from).chain(&t.to)),
+    );
+    for state in candidates {
+      if !states.contains(&state) {
+        states.push(state);
+      }
+    }
+    states
+  }
+
+  pub fn is_final(&self, state: &S) -> bool {
+    self.finals.contains(state)
+  }
+
+  pub fn transitions(&self) -> &[Transition<S, E, C>] {
+    &self.transitions
+  }
+
+  fn select(&self, context: &C, event: &E) -> Result<usize, TransitionError<S, E::Kind>> {
+    let kind = event.kind();
+    let mut candidates = self
+      .transitions
+      .iter()
+      .enumerate()
+      .filter(|(_, t)| t.from == self.state && t.event == kind)
+      .peekable();
+    if candidates.peek().is_none() {
+      return Err(TransitionError::Unhandled {
+        state: self.state.clone(),
+        event: kind,
+      });
+    }
+    candidates
+      .find(|(_, t)| t.guard.as_ref().is_none_or(|g| (g.predicate)(context, event)))
+      .map(|(index, _)| index)
+      .ok_or_else(|| TransitionError::Rejected {
+        state: self.state.clone(),
+        event: kind,
+      })
+  }
+
+  pub fn can_fire(&self, context: &C, event: &E) -> bool {
+    self.select(context, event).is_ok()
+  }
+
+  // 退出フック、アクション、入場フックの順に実行し、遷移後の状態を返す
+  pub fn fire(&mut self, context: &mut C, event: &E) -> Result<&S, TransitionError<S, E::Kind>> {
+    let transition = &self.transitions[self.select(context, event)?];
+    if let Some(to) = &transition.to {
+      run_hooks(&self.exits, &self.state, context);
+      if let Some(action) = &transition.action {
+        action(context, event);
+      }
+      self.state = to.clone();
+      run_hooks(&self.entries, &self.state, context);
+    } else if let Some(action) = &transition.action {
+      action(context, event);
+    }
+    Ok(&self.state)
+  }
+}
+
+fn run_hooks<S: PartialEq, C>(hooks: &[(S, Hook<C>)], state: &S, context: &mut C) {
+  for (_, hook) in hooks.iter().filter(|(s, _)| s == state) {
+    hook(context);
+  }
+}
+
+impl<S: Debug, E: Event, C> Debug for StateMachine<S, E, C> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("StateMachine")
+      .field("state", &self.state)
+      .field("transitions", &self.transitions)
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[derive(Debug, Clone, Copy, PartialEq)]
+  enum Door {
+    Opened,
+    Closed,
+    Locked,
+  }
+
+  #[derive(Debug, Clone, Copy, PartialEq)]
+  enum DoorEvent {
+    Open,
+    Close,
+    Lock(u32),
+    Unlock(u32),
+  }
+
+  impl Event for DoorEvent {
+    type Kind = DoorEvent;
+
+    // 暗証番号は種類に含めない
+    fn kind(&self) -> DoorEvent {
+      match self {
+        DoorEvent::Lock(_) => DoorEvent::Lock(0),
+        DoorEvent::Unlock(_) => DoorEvent::Unlock(0),
+        e => *e,
+      }
+    }
+  }
+
+  #[derive(Debug, Default)]
+  struct Log {
+    code: u32,
+    lines: Vec<String>,
+  }
+
+  fn door() -> StateMachine<Door, DoorEvent, Log> {
+    let log = |line: &'static str| move |log: &mut Log| log.lines.push(line.to_owned());
+    StateMachine::builder()
+      .transitions([
+        Transition::new(Door::Closed, DoorEvent::Open, Door::Opened),
+        Transition::new(Door::Opened, DoorEvent::Close, Door::Closed),
+        Transition::new(Door::Closed, DoorEvent::Lock(0), Door::Locked).with_action(|log: &mut Log, e| {
+          if let DoorEvent::Lock(code) = e {
+            log.code = *code;
+          }
+        }),
+        Transition::new(Door::Locked, DoorEvent::Unlock(0), Door::Closed)
+          .with_guard("暗証番号が一致", |log: &Log, e| {
+            *e == DoorEvent::Unlock(log.code)
+          }),
+        Transition::internal(Door::Opened, DoorEvent::Open)
+          .with_action(|log: &mut Log, _| log.lines.push("already opened".to_owned())),
+      ])
+      .on_exit(Door::Closed, log("exit closed"))
+      .on_entry(Door::Locked, log("enter locked"))
+      .on_entry(Door::Closed, log("enter closed"))
+      .build(Door::Closed)
+  }
+
+  #[test]
+  fn test() {
+    let mut machine = door();
+    let mut log = Log::default();
+    assert_eq!(machine.fire(&mut log, &DoorEvent::Open), Ok(&Door::Opened));
+    assert_eq!(machine.fire(&mut log, &DoorEvent::Open), Ok(&Door::Opened));
+    assert_eq!(machine.fire(&mut log, &DoorEvent::Close), Ok(&Door::Closed));
+    assert_eq!(machine.fire(&mut log, &DoorEvent::Lock(1234)), Ok(&Door::Locked));
+    assert_eq!(log.code, 1234);
+    assert_eq!(
+      log.lines,
+      [
+        "exit closed",
+        "already opened",
+        "enter closed",
+        "exit closed",
+        "enter locked"
+      ]
+    );
+
+    assert!(!machine.can_fire(&log, &DoorEvent::Unlock(1111)));
+    assert!(machine.can_fire(&log, &DoorEvent::Unlock(1234)));
+    assert_eq!(machine.fire(&mut log, &DoorEvent::Unlock(1234)), Ok(&Door::Closed));
+    assert_eq!(machine.transitions()[3].guard(), Some("暗証番号が一致"));
+  }
+
+  #[test]
+  fn test_errors() {
+    let mut machine = door();
+    let mut log = Log::default();
+    let error = machine.fire(&mut log, &DoorEvent::Close).unwrap_err();
+    assert_eq!(
+      error,
+      TransitionError::Unhandled {
+        state: Door::Closed,
+        event: DoorEvent::Close
+      }
+    );
+    assert_eq!(error.to_string(), "no transition from Closed on Close");
+
+    machine.fire(&mut log, &DoorEvent::Lock(1234)).unwrap();
+    let error = machine.fire(&mut log, &DoorEvent::Unlock(4321)).unwrap_err();
+    assert_eq!(
+      error,
+      TransitionError::Rejected {
+        state: Door::Locked,
+        event: DoorEvent::Unlock(0)
+      }
+    );
+    // 失敗した遷移は状態もフックも変えない
+    assert_eq!(machine.state(), &Door::Locked);
+    assert_eq!(log.lines, ["exit closed", "enter locked"]);
+  }
+}
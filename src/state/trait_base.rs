@@ -1,17 +1,180 @@
+use std::any::Any;
+use std::cell::RefCell;
 use std::fmt::{Display, Formatter};
 
 pub trait Context {
   fn set_clock(&mut self, hour: u32);
   fn change_state(&mut self, state: &'static dyn State);
+  // 複合状態へ履歴擬似状態を経由して入り、前回アクティブだった子状態を復元する
+  fn restore_state(&mut self, state: &'static dyn State);
   fn call_security_center(&self, msg: &str);
   fn record_log(&self, msg: &str);
 }
 
-pub trait State: Display {
-  fn do_clock(&self, context: &mut dyn Context, hour: u32);
-  fn do_use(&self, context: &dyn Context);
-  fn do_alarm(&self, context: &dyn Context);
-  fn do_phone(&self, context: &dyn Context);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+  Handled,
+  Unhandled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum History {
+  None,
+  Shallow,
+  Deep,
+}
+
+// 子状態が処理しなかったイベントは親状態へ委ねられる
+pub trait State: Display + Any {
+  fn parent(&self) -> Option<&'static dyn State> {
+    None
+  }
+
+  // 子状態を持つ複合状態は、最初に入る子状態を返す
+  fn initial(&self) -> Option<&'static dyn State> {
+    None
+  }
+
+  fn history(&self) -> History {
+    History::None
+  }
+
+  fn on_entry(&self, _context: &dyn Context) {}
+
+  fn on_exit(&self, _context: &dyn Context) {}
+
+  fn do_clock(&self, _context: &mut dyn Context, _hour: u32) -> Outcome {
+    Outcome::Unhandled
+  }
+
+  fn do_use(&self, _context: &mut dyn Context) -> Outcome {
+    Outcome::Unhandled
+  }
+
+  fn do_alarm(&self, _context: &mut dyn Context) -> Outcome {
+    Outcome::Unhandled
+  }
+
+  fn do_phone(&self, _context: &mut dyn Context) -> Outcome {
+    Outcome::Unhandled
+  }
+}
+
+// 大きさのない状態は同じアドレスを共有し得るので、型も比べる
+fn same(a: &dyn State, b: &dyn State) -> bool {
+  a.type_id() == b.type_id() && std::ptr::addr_eq(a, b)
+}
+
+// 自身を含めた祖先を、近い順に返す
+fn ancestors(state: &'static dyn State) -> Vec<&'static dyn State> {
+  std::iter::successors(Some(state), |s| s.parent()).collect()
+}
+
+fn is_within(state: &'static dyn State, ancestor: &dyn State) -> bool {
+  ancestors(state).iter().any(|s| same(*s, ancestor))
+}
+
+#[derive(Clone, Copy)]
+enum Step {
+  Exit(&'static dyn State),
+  Enter(&'static dyn State),
+}
+
+impl Step {
+  fn run(self, context: &dyn Context) {
+    match self {
+      Step::Exit(state) => state.on_exit(context),
+      Step::Enter(state) => state.on_entry(context),
+    }
+  }
+}
+
+// アクティブな末端の状態と、複合状態ごとに最後にアクティブだった末端の状態を覚えておく
+struct Statechart {
+  active: &'static dyn State,
+  memory: Vec<(&'static dyn State, &'static dyn State)>,
+}
+
+impl Statechart {
+  fn start(state: &'static dyn State) -> (Self, Vec<Step>) {
+    let mut chart = Self {
+      active: state,
+      memory: vec![],
+    };
+    let mut steps = ancestors(state).into_iter().rev().map(Step::Enter).collect::<Vec<_>>();
+    chart.descend(state, false, &mut steps);
+    (chart, steps)
+  }
+
+  fn remembered(&self, composite: &dyn State) -> Option<&'static dyn State> {
+    self
+      .memory
+      .iter()
+      .find(|(c, _)| same(*c, composite))
+      .map(|(_, leaf)| *leaf)
+  }
+
+  fn remember(&mut self, composite: &'static dyn State, leaf: &'static dyn State) {
+    self.memory.retain(|(c, _)| !same(*c, composite));
+    self.memory.push((composite, leaf));
+  }
+
+  fn transition(&mut self, target: &'static dyn State, restore: bool) -> Vec<Step> {
+    // 遷移先の親のうち、現在の状態を含む最も近いものを境に出入りする
+    let boundary = target.parent().and_then(|_| {
+      ancestors(target)
+        .into_iter()
+        .skip(1)
+        .find(|s| is_within(self.active, *s))
+    });
+    let mut steps = vec![];
+    for state in ancestors(self.active) {
+      if boundary.is_some_and(|b| same(state, b)) {
+        break;
+      }
+      if state.initial().is_some() {
+        self.remember(state, self.active);
+      }
+      steps.push(Step::Exit(state));
+    }
+    let entering = ancestors(target)
+      .into_iter()
+      .take_while(|s| boundary.is_none_or(|b| !same(*s, b)))
+      .collect::<Vec<_>>();
+    steps.extend(entering.into_iter().rev().map(Step::Enter));
+    self.descend(target, restore, &mut steps);
+    steps
+  }
+
+  fn descend(&mut self, mut state: &'static dyn State, restore: bool, steps: &mut Vec<Step>) {
+    let remembered = match (restore, state.history()) {
+      (true, History::Shallow) | (true, History::Deep) => self.remembered(state),
+      _ => None,
+    };
+    if let Some(leaf) = remembered {
+      let path = ancestors(leaf)
+        .into_iter()
+        .take_while(|s| !same(*s, state))
+        .collect::<Vec<_>>();
+      let resumed = match state.history() {
+        History::Deep => path.into_iter().rev().collect::<Vec<_>>(),
+        _ => path.into_iter().last().into_iter().collect(),
+      };
+      for s in resumed {
+        steps.push(Step::Enter(s));
+        state = s;
+      }
+    }
+    while let Some(child) = state.initial() {
+      steps.push(Step::Enter(child));
+      state = child;
+    }
+    self.active = state;
+  }
+}
+
+fn is_daytime(hour: u32) -> bool {
+  (9..17).contains(&hour)
 }
 
 struct Day;
@@ -23,25 +186,30 @@ impl Display for Day {
 }
 
 impl State for Day {
-  fn do_clock(&self, context: &mut dyn Context, hour: u32) {
-    if !(9..17).contains(&hour) {
+  fn do_clock(&self, context: &mut dyn Context, hour: u32) -> Outcome {
+    if !is_daytime(hour) {
       context.change_state(&NIGHT);
     }
+    Outcome::Handled
   }
 
-  fn do_use(&self, context: &dyn Context) {
+  fn do_use(&self, context: &mut dyn Context) -> Outcome {
     context.record_log("金庫使用(昼間)");
+    Outcome::Handled
   }
 
-  fn do_alarm(&self, context: &dyn Context) {
+  fn do_alarm(&self, context: &mut dyn Context) -> Outcome {
     context.call_security_center("非常ベル(昼間)");
+    Outcome::Handled
   }
 
-  fn do_phone(&self, context: &dyn Context) {
+  fn do_phone(&self, context: &mut dyn Context) -> Outcome {
     context.record_log("通常の通話(昼間)");
+    Outcome::Handled
   }
 }
 
+// 夜間は警備状態から始まる。警備状態が処理しないイベントは夜間が受け持つ
 struct Night;
 
 impl Display for Night {
@@ -51,65 +219,133 @@ impl Display for Night {
 }
 
 impl State for Night {
-  fn do_clock(&self, context: &mut dyn Context, hour: u32) {
-    if (9..17).contains(&hour) {
+  fn initial(&self) -> Option<&'static dyn State> {
+    Some(&GUARDED)
+  }
+
+  fn do_clock(&self, context: &mut dyn Context, hour: u32) -> Outcome {
+    if is_daytime(hour) {
       context.change_state(&DAY);
     }
+    Outcome::Handled
   }
 
-  fn do_use(&self, context: &dyn Context) {
+  fn do_use(&self, context: &mut dyn Context) -> Outcome {
     context.call_security_center("非常：夜間の金庫使用！");
+    Outcome::Handled
   }
 
-  fn do_alarm(&self, context: &dyn Context) {
+  fn do_alarm(&self, context: &mut dyn Context) -> Outcome {
     context.call_security_center("非常ベル(夜間)");
+    Outcome::Handled
   }
 
-  fn do_phone(&self, context: &dyn Context) {
+  fn do_phone(&self, context: &mut dyn Context) -> Outcome {
     context.record_log("夜間の通話録音");
+    Outcome::Handled
+  }
+}
+
+struct Guarded;
+
+impl Display for Guarded {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "[夜間]")
+  }
+}
+
+impl State for Guarded {
+  fn parent(&self) -> Option<&'static dyn State> {
+    Some(&NIGHT)
   }
 }
 
 static DAY: Day = Day;
 static NIGHT: Night = Night;
+static GUARDED: Guarded = Guarded;
 
 struct StateContext {
-  state: &'static dyn State,
+  chart: Statechart,
+  // 遷移するたびに増える。ハンドラの中で遷移したかどうかを知るために使う
+  transitions: usize,
+  logs: RefCell<Vec<String>>,
 }
 
 impl StateContext {
   fn new(state: &'static dyn State) -> Self {
-    Self { state }
+    let (chart, steps) = Statechart::start(state);
+    let context = Self {
+      chart,
+      transitions: 0,
+      logs: RefCell::new(vec![]),
+    };
+    context.run_steps(steps);
+    context
+  }
+
+  fn state(&self) -> &'static dyn State {
+    self.chart.active
+  }
+
+  fn run_steps(&self, steps: Vec<Step>) {
+    for step in steps {
+      step.run(self);
+    }
+  }
+
+  // アクティブな状態から親へ順に、処理されるまでイベントを渡す。遷移したハンドラは処理したものとみなす
+  fn dispatch(&mut self, event: impl Fn(&'static dyn State, &mut dyn Context) -> Outcome) -> Outcome {
+    let mut state = Some(self.state());
+    while let Some(current) = state {
+      let transitions = self.transitions;
+      if event(current, self) == Outcome::Handled || self.transitions != transitions {
+        return Outcome::Handled;
+      }
+      state = current.parent();
+    }
+    Outcome::Unhandled
   }
 
   fn run(&mut self) {
     for hour in 0..=24 {
       self.set_clock(hour);
       match hour % 3 {
-        0 => self.state.do_use(self),
-        1 => self.state.do_alarm(self),
-        2 => self.state.do_phone(self),
+        0 => self.dispatch(|s, c| s.do_use(c)),
+        1 => self.dispatch(|s, c| s.do_alarm(c)),
+        2 => self.dispatch(|s, c| s.do_phone(c)),
         _ => unreachable!(),
-      }
+      };
     }
   }
 }
 
 impl Context for StateContext {
   fn set_clock(&mut self, hour: u32) {
-    self.state.do_clock(self, hour);
+    self.dispatch(|s, c| s.do_clock(c, hour));
   }
 
   fn change_state(&mut self, state: &'static dyn State) {
-    self.state = state;
+    let steps = self.chart.transition(state, false);
+    self.transitions += 1;
+    self.run_steps(steps);
+  }
+
+  fn restore_state(&mut self, state: &'static dyn State) {
+    let steps = self.chart.transition(state, true);
+    self.transitions += 1;
+    self.run_steps(steps);
   }
 
   fn call_security_center(&self, msg: &str) {
-    println!("{}:{}", self.state, msg);
+    let line = format!("{}:{}", self.state(), msg);
+    println!("{}", line);
+    self.logs.borrow_mut().push(line);
   }
 
   fn record_log(&self, msg: &str) {
-    println!("{}:{}", self.state, msg);
+    let line = format!("{}:{}", self.state(), msg);
+    println!("{}", line);
+    self.logs.borrow_mut().push(line);
   }
 }
 
@@ -121,5 +357,203 @@ mod test {
   fn test() {
     let mut context = StateContext::new(&DAY);
     context.run();
+    let logs = context.logs.borrow();
+    assert_eq!(
+      logs[..3],
+      [
+        "[夜間]:非常：夜間の金庫使用！",
+        "[夜間]:非常ベル(夜間)",
+        "[夜間]:夜間の通話録音"
+      ]
+    );
+    assert!(logs.contains(&"[昼間]:金庫使用(昼間)".to_owned()));
+    assert!(same(context.state(), &GUARDED));
+  }
+
+  // 入退場を記録するだけの、階層構造を確かめるための状態
+  struct Node {
+    name: &'static str,
+    parent: Option<&'static Node>,
+    initial: Option<&'static Node>,
+    history: History,
+  }
+
+  impl Display for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+      write!(f, "{}", self.name)
+    }
+  }
+
+  impl State for Node {
+    fn parent(&self) -> Option<&'static dyn State> {
+      self.parent.map(|p| p as &dyn State)
+    }
+
+    fn initial(&self) -> Option<&'static dyn State> {
+      self.initial.map(|i| i as &dyn State)
+    }
+
+    fn history(&self) -> History {
+      self.history
+    }
+
+    fn on_entry(&self, context: &dyn Context) {
+      context.record_log(&format!("enter {}", self.name));
+    }
+
+    fn on_exit(&self, context: &dyn Context) {
+      context.record_log(&format!("exit {}", self.name));
+    }
+  }
+
+  const fn node(
+    name: &'static str,
+    parent: Option<&'static Node>,
+    initial: Option<&'static Node>,
+    history: History,
+  ) -> Node {
+    Node {
+      name,
+      parent,
+      initial,
+      history,
+    }
+  }
+
+  // OFF と ON があり、ON の中に RADIO(FM/AM) と PLAYER のモードがある
+  static OFF: Node = node("off", None, None, History::None);
+  static ON: Node = node("on", None, Some(&RADIO), History::Shallow);
+  static RADIO: Node = node("radio", Some(&ON), Some(&FM), History::None);
+  static FM: Node = node("fm", Some(&RADIO), None, History::None);
+  static AM: Node = node("am", Some(&RADIO), None, History::None);
+  static PLAYER: Node = node("player", Some(&ON), None, History::None);
+  static DEEP_ON: Node = node("deep", None, Some(&DEEP_RADIO), History::Deep);
+  static DEEP_RADIO: Node = node("deep radio", Some(&DEEP_ON), Some(&DEEP_FM), History::None);
+  static DEEP_FM: Node = node("deep fm", Some(&DEEP_RADIO), None, History::None);
+  static DEEP_AM: Node = node("deep am", Some(&DEEP_RADIO), None, History::None);
+
+  // 前回の呼び出し以降に記録された入退場だけを返す
+  fn drain(context: &StateContext) -> Vec<String> {
+    context
+      .logs
+      .take()
+      .iter()
+      .map(|l| l.split_once(':').unwrap().1.to_owned())
+      .collect()
+  }
+
+  #[test]
+  fn test_hierarchy() {
+    let mut context = StateContext::new(&ON);
+    assert_eq!(drain(&context), ["enter on", "enter radio", "enter fm"]);
+    // 兄弟への遷移では、共通の親は出入りしない
+    context.change_state(&AM);
+    assert_eq!(drain(&context), ["exit fm", "enter am"]);
+    context.change_state(&AM);
+    assert_eq!(drain(&context), ["exit am", "enter am"]);
+    context.change_state(&RADIO);
+    assert_eq!(drain(&context), ["exit am", "exit radio", "enter radio", "enter fm"]);
+    context.change_state(&OFF);
+    assert_eq!(drain(&context), ["exit fm", "exit radio", "exit on", "enter off"]);
+    assert!(same(context.state(), &OFF));
+
+    // 子状態が処理しないイベントは親状態へ伝わり、どこも処理しなければ未処理になる
+    assert_eq!(context.dispatch(|s, c| s.do_use(c)), Outcome::Unhandled);
+  }
+
+  #[test]
+  fn test_history() {
+    let mut context = StateContext::new(&ON);
+    context.change_state(&AM);
+    context.change_state(&OFF);
+    drain(&context);
+    // 浅い履歴は直下の子状態だけを復元し、その先は初期状態から入る
+    context.restore_state(&ON);
+    assert_eq!(drain(&context), ["exit off", "enter on", "enter radio", "enter fm"]);
+    context.change_state(&PLAYER);
+    context.change_state(&OFF);
+    drain(&context);
+    context.restore_state(&ON);
+    assert_eq!(drain(&context), ["exit off", "enter on", "enter player"]);
+    context.change_state(&OFF);
+    context.change_state(&ON);
+    assert_eq!(
+      drain(&context),
+      [
+        "exit player",
+        "exit on",
+        "enter off",
+        "exit off",
+        "enter on",
+        "enter radio",
+        "enter fm"
+      ]
+    );
+
+    // 深い履歴は末端の状態までそのまま復元する
+    context.change_state(&DEEP_AM);
+    context.change_state(&OFF);
+    drain(&context);
+    context.restore_state(&DEEP_ON);
+    assert_eq!(
+      drain(&context),
+      ["exit off", "enter deep", "enter deep radio", "enter deep am"]
+    );
+    // 履歴擬似状態を経由しなければ初期状態から入る
+    context.change_state(&OFF);
+    context.change_state(&DEEP_ON);
+    assert!(same(context.state(), &DEEP_FM));
+  }
+
+  // 子状態は遷移してから処理しなかったと答え、親状態は自分で処理する
+  struct Caller;
+
+  impl Display for Caller {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+      write!(f, "caller")
+    }
+  }
+
+  impl State for Caller {
+    fn initial(&self) -> Option<&'static dyn State> {
+      Some(&LEAVING)
+    }
+
+    fn do_phone(&self, context: &mut dyn Context) -> Outcome {
+      context.record_log("caller handled");
+      Outcome::Handled
+    }
+  }
+
+  struct Leaving;
+
+  impl Display for Leaving {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+      write!(f, "leaving")
+    }
+  }
+
+  impl State for Leaving {
+    fn parent(&self) -> Option<&'static dyn State> {
+      Some(&CALLER)
+    }
+
+    fn do_phone(&self, context: &mut dyn Context) -> Outcome {
+      context.change_state(&OFF);
+      Outcome::Unhandled
+    }
+  }
+
+  static CALLER: Caller = Caller;
+  static LEAVING: Leaving = Leaving;
+
+  #[test]
+  fn test_dispatch_after_transition() {
+    let mut context = StateContext::new(&CALLER);
+    // 遷移した後のイベントは、もう元の状態の親へは渡らない
+    assert_eq!(context.dispatch(|s, c| s.do_phone(c)), Outcome::Handled);
+    assert!(same(context.state(), &OFF));
+    assert_eq!(drain(&context), ["enter off"]);
+    assert_eq!(context.dispatch(|s, c| s.do_phone(c)), Outcome::Unhandled);
   }
 }
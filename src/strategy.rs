@@ -60,3 +60,59 @@ impl Display for Hand {
     write!(f, "{}", self.name())
   }
 }
+
+// enum_baseとtrait_baseのテストで共有する対戦の進め方と検証
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // 各モジュールのPlayerは、それぞれのHandを使う
+  pub(super) trait Contestant: Display {
+    type Hand: Copy;
+
+    fn is_stronger(hand: Self::Hand, other: Self::Hand) -> bool;
+    fn next_hand(&mut self) -> Self::Hand;
+    fn win(&mut self);
+    fn lose(&mut self);
+    fn even(&mut self);
+  }
+
+  pub(super) fn play<P: Contestant>(player1: &mut P, player2: &mut P, rounds: usize) {
+    for _ in 0..rounds {
+      let next_hand1 = player1.next_hand();
+      let next_hand2 = player2.next_hand();
+      if P::is_stronger(next_hand1, next_hand2) {
+        player1.win();
+        player2.lose();
+      } else if P::is_stronger(next_hand2, next_hand1) {
+        player1.lose();
+        player2.win();
+      } else {
+        player1.even();
+        player2.even();
+      }
+    }
+  }
+
+  // 台本の乱数で手を決めるWinningStrategy同士の勝敗が、台本どおりになること
+  pub(super) fn check_scripted<P: Contestant>(winning: impl Fn(&str, [u32; 2]) -> P) {
+    let mut player1 = winning("Taro", [0, 1]);
+    let mut player2 = winning("Hana", [2, 1]);
+    // グー対チョキ、グー対パー、パー対パー、グー対パー
+    play(&mut player1, &mut player2, 4);
+    assert_eq!(player1.to_string(), "[Taro: 4 games, 1 win, 2 lose]");
+    assert_eq!(player2.to_string(), "[Hana: 4 games, 2 win, 1 lose]");
+  }
+
+  // 同じシードから作った対戦は、何度行っても同じ結果になること
+  pub(super) fn check_seeded<P: Contestant>(players: impl Fn() -> (P, P)) {
+    let results = (0..2)
+      .map(|_| {
+        let (mut player1, mut player2) = players();
+        play(&mut player1, &mut player2, 1000);
+        (player1.to_string(), player2.to_string())
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(results[0], results[1]);
+  }
+}
@@ -1,6 +1,7 @@
-use rand::prelude::*;
 use std::fmt::{Display, Formatter};
 
+use crate::random::{self, NumberSource};
+
 #[derive(Debug, Clone, Copy)]
 pub enum Hand {
   Rock,
@@ -28,12 +29,12 @@ impl Hand {
 #[derive(Debug)]
 pub enum Strategy {
   Winning {
-    rng: ThreadRng,
+    rng: Box<dyn NumberSource>,
     won: bool,
     prev_hand: Hand,
   },
   Probe {
-    rng: ThreadRng,
+    rng: Box<dyn NumberSource>,
     prev_hand_value: u8,
     current_hand_value: u8,
     history: [[u32; 3]; 3],
@@ -42,16 +43,32 @@ pub enum Strategy {
 
 impl Strategy {
   pub fn of_winning() -> Self {
+    Self::of_winning_with_rng(rand::rng())
+  }
+
+  pub fn of_winning_with_seed(seed: u64) -> Self {
+    Self::of_winning_with_rng(random::seeded(seed))
+  }
+
+  pub fn of_winning_with_rng(rng: impl NumberSource + 'static) -> Self {
     Strategy::Winning {
-      rng: rand::thread_rng(),
+      rng: Box::new(rng),
       won: false,
       prev_hand: Hand::Rock,
     }
   }
 
   pub fn of_probe() -> Self {
+    Self::of_probe_with_rng(rand::rng())
+  }
+
+  pub fn of_probe_with_seed(seed: u64) -> Self {
+    Self::of_probe_with_rng(random::seeded(seed))
+  }
+
+  pub fn of_probe_with_rng(rng: impl NumberSource + 'static) -> Self {
     Strategy::Probe {
-      rng: rand::thread_rng(),
+      rng: Box::new(rng),
       prev_hand_value: 0,
       current_hand_value: 0,
       history: [[1; 3]; 3],
@@ -66,7 +83,7 @@ impl Strategy {
     match self {
       Strategy::Winning { rng, won, prev_hand } => {
         if !*won {
-          *prev_hand = Hand::get_hand(rng.gen_range(0..=2));
+          *prev_hand = Hand::get_hand(rng.next_in(0..=2) as u8);
         }
         *prev_hand
       }
@@ -76,7 +93,7 @@ impl Strategy {
         current_hand_value,
         history,
       } => {
-        let bet = rng.gen_range(0..=Self::get_sum(history, *current_hand_value));
+        let bet = rng.next_in(0..=Self::get_sum(history, *current_hand_value));
         let hand_value = if bet < history[*current_hand_value as usize][0] {
           0
         } else if bet < history[*current_hand_value as usize][0] + history[*current_hand_value as usize][1] {
@@ -165,6 +182,47 @@ impl Player {
 #[cfg(test)]
 mod test {
   use super::*;
+  use crate::random::Scripted;
+  use crate::strategy::test::{check_scripted, check_seeded, Contestant};
+
+  impl Contestant for Player {
+    type Hand = Hand;
+
+    fn is_stronger(hand: Hand, other: Hand) -> bool {
+      hand.is_stronger_than(other)
+    }
+
+    fn next_hand(&mut self) -> Hand {
+      Player::next_hand(self)
+    }
+
+    fn win(&mut self) {
+      Player::win(self)
+    }
+
+    fn lose(&mut self) {
+      Player::lose(self)
+    }
+
+    fn even(&mut self) {
+      Player::even(self)
+    }
+  }
+
+  #[test]
+  fn test_scripted() {
+    check_scripted(|name, script| Player::new(name, Strategy::of_winning_with_rng(Scripted::new(script))));
+  }
+
+  #[test]
+  fn test_seeded() {
+    check_seeded(|| {
+      (
+        Player::new("Taro", Strategy::of_winning_with_seed(1)),
+        Player::new("Hana", Strategy::of_probe_with_seed(2)),
+      )
+    });
+  }
 
   #[test]
   fn test() {
@@ -1,7 +1,8 @@
 use rand::prelude::*;
-use rand::Rng;
 use std::fmt::{Display, Formatter};
 
+use crate::random::{self, NumberSource};
+
 #[derive(Clone, Copy, Debug)]
 pub enum Hand {
   Rock,
@@ -32,16 +33,16 @@ pub trait Strategy {
 }
 
 #[derive(Clone, Debug)]
-pub struct WinningStrategy {
-  rng: ThreadRng,
+pub struct WinningStrategy<R = ThreadRng> {
+  rng: R,
   won: bool,
   prev_hand: Hand,
 }
 
-impl Strategy for WinningStrategy {
+impl<R: NumberSource> Strategy for WinningStrategy<R> {
   fn next_hand(&mut self) -> Hand {
     if !self.won {
-      self.prev_hand = Hand::get_hand(self.rng.gen_range(0..=2))
+      self.prev_hand = Hand::get_hand(self.rng.next_in(0..=2) as u8)
     }
     self.prev_hand
   }
@@ -53,8 +54,20 @@ impl Strategy for WinningStrategy {
 
 impl WinningStrategy {
   pub fn new() -> Self {
+    Self::with_rng(rand::rng())
+  }
+}
+
+impl WinningStrategy<StdRng> {
+  pub fn with_seed(seed: u64) -> Self {
+    Self::with_rng(random::seeded(seed))
+  }
+}
+
+impl<R: NumberSource> WinningStrategy<R> {
+  pub fn with_rng(rng: R) -> Self {
     Self {
-      rng: rand::thread_rng(),
+      rng,
       won: false,
       prev_hand: Hand::Rock,
     }
@@ -62,16 +75,16 @@ impl WinningStrategy {
 }
 
 #[derive(Clone, Debug)]
-pub struct ProbeStrategy {
-  rng: ThreadRng,
+pub struct ProbeStrategy<R = ThreadRng> {
+  rng: R,
   prev_hand_value: u8,
   current_hand_value: u8,
   history: [[u32; 3]; 3],
 }
 
-impl Strategy for ProbeStrategy {
+impl<R: NumberSource> Strategy for ProbeStrategy<R> {
   fn next_hand(&mut self) -> Hand {
-    let bet = self.rng.gen_range(0..=self.get_sum(self.current_hand_value));
+    let bet = self.rng.next_in(0..=self.get_sum(self.current_hand_value));
     let hand_value = if bet < self.history[self.current_hand_value as usize][0] {
       0
     } else if bet
@@ -97,13 +110,25 @@ impl Strategy for ProbeStrategy {
 }
 
 impl ProbeStrategy {
+  pub fn new() -> Self {
+    Self::with_rng(rand::rng())
+  }
+}
+
+impl ProbeStrategy<StdRng> {
+  pub fn with_seed(seed: u64) -> Self {
+    Self::with_rng(random::seeded(seed))
+  }
+}
+
+impl<R: NumberSource> ProbeStrategy<R> {
   fn get_sum(&self, hand_value: u8) -> u32 {
     self.history[hand_value as usize].iter().sum()
   }
 
-  pub fn new() -> Self {
+  pub fn with_rng(rng: R) -> Self {
     Self {
-      rng: rand::thread_rng(),
+      rng,
       prev_hand_value: 0,
       current_hand_value: 0,
       history: [[1; 3]; 3],
@@ -164,6 +189,47 @@ impl Player {
 #[cfg(test)]
 mod test {
   use super::*;
+  use crate::random::Scripted;
+  use crate::strategy::test::{check_scripted, check_seeded, Contestant};
+
+  impl Contestant for Player {
+    type Hand = Hand;
+
+    fn is_stronger(hand: Hand, other: Hand) -> bool {
+      hand.is_stronger_than(other)
+    }
+
+    fn next_hand(&mut self) -> Hand {
+      Player::next_hand(self)
+    }
+
+    fn win(&mut self) {
+      Player::win(self)
+    }
+
+    fn lose(&mut self) {
+      Player::lose(self)
+    }
+
+    fn even(&mut self) {
+      Player::even(self)
+    }
+  }
+
+  #[test]
+  fn test_scripted() {
+    check_scripted(|name, script| Player::new(name, Box::new(WinningStrategy::with_rng(Scripted::new(script)))));
+  }
+
+  #[test]
+  fn test_seeded() {
+    check_seeded(|| {
+      (
+        Player::new("Taro", Box::new(WinningStrategy::with_seed(1))),
+        Player::new("Hana", Box::new(ProbeStrategy::with_seed(2))),
+      )
+    });
+  }
 
   #[test]
   fn test() {